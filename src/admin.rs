@@ -0,0 +1,145 @@
+use std::io;
+use std::net::SocketAddr;
+
+use log::info;
+use serde::Serialize;
+
+use crate::events::publish;
+use crate::link::LinkState;
+use crate::qso::QsoEndReason;
+use crate::transcode::AudioFormat;
+use crate::utils::secret_eq;
+use crate::websocket::{InfoEvent, SessionID, StreamTarget, WS_SESSIONS};
+use crate::{module_info, ReflectorConnection, CFG, REFLECTOR_CONNECTIONS};
+
+/// A module as listed by the admin API.
+#[derive(Serialize)]
+struct ModuleEntry {
+    reflector: String,
+    module: String,
+    address: String,
+    state: LinkState,
+    transmit: bool,
+    on_demand: bool,
+}
+
+/// A connected WebSocket client as listed by the admin API.
+#[derive(Serialize)]
+struct SessionEntry {
+    id: SessionID,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    remote_address: SocketAddr,
+    user_agent: Option<String>,
+    connected_at: u64,
+    subscriptions: Vec<StreamTarget>,
+    format: Option<AudioFormat>,
+    tx_callsign: Option<String>,
+}
+
+/// Whether the admin API is enabled with `M17WEB_PROXY_ADMIN_TOKEN`.
+pub fn enabled() -> bool {
+    !CFG.admin_token.is_empty()
+}
+
+/// Check the value of an `Authorization: Bearer <token>` header.
+pub fn authorized(authorization: Option<&str>) -> bool {
+    enabled() && authorization
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|token| secret_eq(token.trim(), &CFG.admin_token))
+}
+
+/// Normalize a reflector designator and module letter given by an admin.
+pub fn parse_module(reflector: &str, module: &str) -> Result<(String, String), String> {
+    let reflector = reflector.to_uppercase();
+    if reflector.is_empty() || reflector.len() > 16 || !reflector.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return Err(format!("Invalid reflector designator: {}", reflector));
+    }
+    let module = module.to_uppercase();
+    if module.len() != 1 || !module.chars().all(|c| c.is_ascii_uppercase()) {
+        return Err(format!("Invalid module: {}", module));
+    }
+    Ok((reflector, module))
+}
+
+/// All modules with their link state, as JSON.
+pub async fn modules() -> String {
+    let modules: Vec<ModuleEntry> = REFLECTOR_CONNECTIONS.lock().await.iter()
+        .map(|c| ModuleEntry {
+            reflector: c.reflector.clone(),
+            module: c.module.clone(),
+            address: c.address.clone(),
+            state: c.link.state,
+            transmit: c.transmit,
+            on_demand: c.on_demand,
+        })
+        .collect();
+    serde_json::to_string(&modules).unwrap()
+}
+
+/// All connected WebSocket clients, oldest first, as JSON.
+pub async fn sessions() -> String {
+    let mut sessions: Vec<SessionEntry> = WS_SESSIONS.lock().await.values()
+        .map(|s| {
+            let stream = !s.info_connection;
+            SessionEntry {
+                id: s.ws_session.id,
+                kind: if stream { "stream" } else { "info" },
+                remote_address: s.remote_address,
+                user_agent: s.user_agent.clone(),
+                connected_at: s.connected_at,
+                subscriptions: s.subscriptions.iter().cloned().collect(),
+                format: stream.then_some(s.format),
+                tx_callsign: s.tx_callsign.clone(),
+            }
+        })
+        .collect();
+    sessions.sort_by_key(|s| s.id);
+    serde_json::to_string(&sessions).unwrap()
+}
+
+/// Add a module at runtime. It is resolved and linked on the next housekeeping tick.
+/// Returns false if it already exists; adding an on-demand module permanently keeps it linked.
+pub async fn add_module(reflector: &str, module: &str, on_demand: bool) -> io::Result<bool> {
+    let mut connections = REFLECTOR_CONNECTIONS.lock().await;
+    if let Some(existing) = connections.iter_mut().find(|c| c.reflector == reflector && c.module == module) {
+        existing.on_demand &= on_demand;
+        return Ok(false);
+    }
+
+    let mut connection = ReflectorConnection::new(reflector, module).await?;
+    connection.on_demand = on_demand;
+    info!("Added {} Module {}{}", reflector, module, if on_demand { " on demand" } else { "" });
+    publish(&InfoEvent::ModuleAdded { module: module_info(&connection) }).await;
+    connections.push(connection);
+    Ok(true)
+}
+
+/// Unlink and remove a module at runtime. Returns false if it does not exist.
+pub async fn remove_module(reflector: &str, module: &str) -> bool {
+    let mut connections = REFLECTOR_CONNECTIONS.lock().await;
+    let Some(index) = connections.iter().position(|c| c.reflector == reflector && c.module == module) else {
+        return false;
+    };
+    unlink_module(&mut connections, index).await;
+    true
+}
+
+/// Remove the connection at `index`, sending DISC and notifying info clients.
+pub async fn unlink_module(connections: &mut Vec<ReflectorConnection>, index: usize) {
+    let mut connection = connections.remove(index);
+    let (reflector, module) = (connection.reflector.clone(), connection.module.clone());
+    for qso in connection.unlink(QsoEndReason::Unlinked).await {
+        publish(&InfoEvent::QsoEnd {
+            reflector: reflector.clone(),
+            module: module.clone(),
+            reason: QsoEndReason::Unlinked,
+            qso,
+        }).await;
+    }
+    info!("Removed {} Module {}", reflector, module);
+    publish(&InfoEvent::ModuleRemoved { reflector: reflector.clone(), module: module.clone() }).await;
+
+    #[cfg(feature = "transcode")]
+    crate::listen::close(&reflector, &module).await;
+}
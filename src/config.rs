@@ -5,6 +5,8 @@ pub struct Config {
     pub callsign: String,
     #[envconfig(from = "M17WEB_PROXY_LISTENER", default = "0.0.0.0:3000")]
     pub ws_listener_address: String,
+    #[envconfig(from = "M17WEB_PROXY_HTTP_LISTENER", default = "0.0.0.0:3001")]
+    pub http_listener_address: String,
     #[envconfig(from = "M17WEB_PROXY_SUBSCRIPTION", default = "M17-XOR_ABC")]
     pub subscription: String,
     #[envconfig(from = "M17WEB_PROXY_DHT_BOOTSTRAP", default = "xrf757.openquad.net")]
@@ -13,4 +15,40 @@ pub struct Config {
     pub dht_port: String,
     #[envconfig(from = "M17WEB_PROXY_HOSTFILE_URL", default = "https://hostfiles.refcheck.radio/M17Hosts.json")]
     pub hostfile_url: String,
+    #[envconfig(from = "M17WEB_PROXY_TX_MODULES", default = "")]
+    pub tx_modules: String,
+    #[envconfig(from = "M17WEB_PROXY_TX_USERS", default = "")]
+    pub tx_users: String,
+    #[envconfig(from = "M17WEB_PROXY_TX_CAN", default = "0")]
+    pub tx_can: u8,
+    #[envconfig(from = "M17WEB_PROXY_SMS_INTERVAL", default = "10")]
+    pub sms_interval: u64,
+    #[envconfig(from = "M17WEB_PROXY_DATABASE", default = "")]
+    pub database: String,
+    #[envconfig(from = "M17WEB_PROXY_MESSAGE_LIMIT", default = "100")]
+    pub message_limit: usize,
+    #[envconfig(from = "M17WEB_PROXY_MESSAGE_MAX_AGE", default = "86400")]
+    pub message_max_age: u64,
+    #[envconfig(from = "M17WEB_PROXY_POSITION_MAX_AGE", default = "3600")]
+    pub position_max_age: u64,
+    #[envconfig(from = "M17WEB_PROXY_EVENT_BUFFER", default = "1000")]
+    pub event_buffer: usize,
+    #[envconfig(from = "M17WEB_PROXY_RECORD_DIR", default = "")]
+    pub record_dir: String,
+    #[envconfig(from = "M17WEB_PROXY_RECORD_MODULES", default = "")]
+    pub record_modules: String,
+    #[envconfig(from = "M17WEB_PROXY_RECORD_FORMAT", default = "c2")]
+    pub record_format: String,
+    #[envconfig(from = "M17WEB_PROXY_RECORD_QUOTA_MB", default = "1024")]
+    pub record_quota_mb: u64,
+    #[envconfig(from = "M17WEB_PROXY_RECORD_MAX_AGE_DAYS", default = "30")]
+    pub record_max_age_days: u64,
+    #[envconfig(from = "M17WEB_PROXY_REPLAY_TRANSMISSIONS", default = "10")]
+    pub replay_transmissions: usize,
+    #[envconfig(from = "M17WEB_PROXY_ADMIN_TOKEN", default = "")]
+    pub admin_token: String,
+    #[envconfig(from = "M17WEB_PROXY_ON_DEMAND", default = "")]
+    pub on_demand: String,
+    #[envconfig(from = "M17WEB_PROXY_ON_DEMAND_IDLE", default = "300")]
+    pub on_demand_idle: u64,
 }
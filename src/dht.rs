@@ -122,6 +122,16 @@ unsafe extern "C" fn get_done_callback(ok: bool, user_data: *mut libc::c_void) {
     }
 }
 
+/// C callback invoked when `dht_runner_shutdown` completes.
+unsafe extern "C" fn shutdown_done_callback(user_data: *mut libc::c_void) {
+    if user_data.is_null() {
+        return;
+    }
+
+    let tx = Box::from_raw(user_data as *mut oneshot::Sender<()>);
+    let _ = tx.send(());
+}
+
 impl DhtNode {
     /// Create a new DHT node and start it on the given port with a generated identity.
     pub fn new(port: u16, identity_name: &str) -> Result<Self, String> {
@@ -225,6 +235,19 @@ impl DhtNode {
             )),
         }
     }
+
+    /// Stop the DHT runner. Returns once it has finished its pending operations.
+    pub async fn shutdown(&self) {
+        let (tx, rx) = oneshot::channel::<()>();
+        let tx_ptr = Box::into_raw(Box::new(tx)) as *mut libc::c_void;
+
+        unsafe {
+            opendht_sys::dht_runner_shutdown(self.runner, Some(shutdown_done_callback), tx_ptr);
+        }
+
+        let _ = rx.await;
+        info!("DHT: Node shut down");
+    }
 }
 
 impl Drop for DhtNode {
@@ -0,0 +1,152 @@
+use std::collections::VecDeque;
+
+use lazy_static::lazy_static;
+use serde::Serialize;
+use tokio::sync::Mutex;
+
+use crate::websocket::{InfoEvent, ModuleInfo, M17ClientSession, WS_SESSIONS};
+use crate::{get_epoch, metrics, module_info, CFG, REFLECTOR_CONNECTIONS};
+
+lazy_static! {
+    static ref EVENT_LOG: Mutex<EventLog> = Mutex::new(EventLog::default());
+    /// Identifies this process run, sequence numbers are only comparable within a run.
+    static ref RUN: u64 = get_epoch().as_millis() as u64;
+}
+
+/// Info event as sent on the wire, tagged with the run and its sequence number.
+#[derive(Serialize)]
+struct SequencedEvent<'a> {
+    run: u64,
+    seq: u64,
+    #[serde(flatten)]
+    event: &'a InfoEvent,
+}
+
+/// Full state of all modules, sent to info clients that cannot resume.
+/// Events with a sequence number above `seq` follow.
+#[derive(Serialize)]
+struct Snapshot {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    run: u64,
+    seq: u64,
+    modules: Vec<ModuleInfo>,
+}
+
+/// The most recent serialized info events, kept so reconnecting clients can resume.
+#[derive(Default)]
+struct EventLog {
+    seq: u64,
+    events: VecDeque<(u64, String)>,
+}
+
+impl EventLog {
+    fn push(&mut self, event: &InfoEvent) -> String {
+        self.seq += 1;
+        let text = serde_json::to_string(&SequencedEvent { run: *RUN, seq: self.seq, event }).unwrap();
+        self.events.push_back((self.seq, text.clone()));
+        while self.events.len() > CFG.event_buffer {
+            self.events.pop_front();
+        }
+        text
+    }
+
+    /// Events after `since` of the given run, or None if some of them were
+    /// already dropped or the client saw a previous run.
+    fn since(&self, run: u64, since: u64) -> Option<Vec<String>> {
+        if run != *RUN || since > self.seq {
+            return None;
+        }
+        let oldest = self.events.front().map_or(self.seq + 1, |(seq, _)| *seq);
+        if since + 1 < oldest {
+            return None;
+        }
+        Some(self.events.iter()
+            .filter(|(seq, _)| *seq > since)
+            .map(|(_, text)| text.clone())
+            .collect())
+    }
+}
+
+/// Assign the next sequence number to an event and send it to all info clients.
+pub async fn publish(event: &InfoEvent) {
+    let mut log = EVENT_LOG.lock().await;
+    let text = log.push(event);
+    for session in WS_SESSIONS.lock().await.values() {
+        if session.info_connection {
+            metrics::bytes_sent("info", text.len());
+            let _ = session.ws_session.handle.text(text.clone());
+        }
+    }
+}
+
+/// Register an info client. It receives the events after `since` if they are
+/// from this run and still buffered, otherwise a snapshot of all modules.
+pub async fn attach_info_client(session: M17ClientSession, resume: Option<(u64, u64)>) {
+    // Same lock order as the frame handler, so no event falls between snapshot and registration
+    let connections = REFLECTOR_CONNECTIONS.lock().await;
+    let log = EVENT_LOG.lock().await;
+
+    match resume.and_then(|(run, since)| log.since(run, since)) {
+        Some(events) => {
+            for text in events {
+                metrics::bytes_sent("info", text.len());
+                let _ = session.ws_session.handle.text(text);
+            }
+        }
+        None => {
+            let snapshot = Snapshot {
+                kind: "snapshot",
+                run: *RUN,
+                seq: log.seq,
+                modules: connections.iter().map(module_info).collect(),
+            };
+            let text = serde_json::to_string(&snapshot).unwrap();
+            metrics::bytes_sent("info", text.len());
+            let _ = session.ws_session.handle.text(text);
+        }
+    }
+
+    WS_SESSIONS.lock().await.insert(session.ws_session.id, session);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push(log: &mut EventLog, module: &str) {
+        log.push(&InfoEvent::ModuleRemoved { reflector: "M17-XOR".to_string(), module: module.to_string() });
+    }
+
+    #[test]
+    fn resume_within_run() {
+        let mut log = EventLog::default();
+        for module in ["A", "B", "C"] {
+            push(&mut log, module);
+        }
+
+        let events = log.since(*RUN, 1).unwrap();
+        assert_eq!(events.len(), 2);
+        assert!(events[0].contains("\"seq\":2") && events[0].contains("\"module\":\"B\""));
+        assert!(events[0].contains(&format!("\"run\":{}", *RUN)));
+        assert_eq!(log.since(*RUN, 3), Some(vec![]));
+        assert_eq!(log.since(*RUN, 0).map(|events| events.len()), Some(3));
+    }
+
+    #[test]
+    fn snapshot_needed() {
+        let mut log = EventLog::default();
+        push(&mut log, "A");
+
+        // Ahead of the counter, or sequence numbers of another run
+        assert_eq!(log.since(*RUN, 2), None);
+        assert_eq!(log.since(*RUN + 1, 0), None);
+
+        // Some of the missed events were dropped
+        for _ in 0..CFG.event_buffer {
+            push(&mut log, "B");
+        }
+        assert_eq!(log.since(*RUN, 0), None);
+        assert_eq!(log.since(*RUN, 1).map(|events| events.len()), Some(CFG.event_buffer));
+    }
+}
@@ -0,0 +1,49 @@
+use serde::Serialize;
+
+use crate::link::LinkState;
+use crate::metrics::{discovery, DiscoveryStatus};
+use crate::{get_epoch, LINK_TIMEOUT, REFLECTOR_CONNECTIONS};
+
+/// State of a single reflector link.
+#[derive(Serialize)]
+struct LinkHealth {
+    reflector: String,
+    module: String,
+    address: String,
+    linked: bool,
+    state: LinkState,
+    last_heard: u64,
+    /// Linked and heard from the reflector within the link timeout.
+    ready: bool,
+}
+
+#[derive(Serialize)]
+struct Readiness {
+    ready: bool,
+    links: Vec<LinkHealth>,
+    discovery: DiscoveryStatus,
+}
+
+/// Readiness of the proxy: at least one link received ACKN or PING within the link timeout.
+/// Returns the verdict and a JSON body describing each link.
+pub async fn readiness() -> (bool, String) {
+    let now = get_epoch().as_secs();
+    let links: Vec<LinkHealth> = REFLECTOR_CONNECTIONS.lock().await.iter()
+        .map(|c| LinkHealth {
+            reflector: c.reflector.clone(),
+            module: c.module.clone(),
+            address: c.address.clone(),
+            linked: c.link.state == LinkState::Linked,
+            state: c.link.state,
+            last_heard: c.last_heard,
+            ready: c.link.state == LinkState::Linked && now.saturating_sub(c.last_heard) <= LINK_TIMEOUT,
+        })
+        .collect();
+
+    let readiness = Readiness {
+        ready: links.iter().any(|link| link.ready),
+        links,
+        discovery: discovery(),
+    };
+    (readiness.ready, serde_json::to_string(&readiness).unwrap())
+}
@@ -0,0 +1,147 @@
+use std::convert::Infallible;
+
+use bytes::Bytes;
+use http_body_util::combinators::UnsyncBoxBody;
+use http_body_util::{BodyExt, Full};
+use hyper::body::Incoming;
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE, WWW_AUTHENTICATE};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use log::{debug, error, info};
+use tokio::net::TcpListener;
+
+use crate::{admin, health, metrics};
+
+pub type Body = UnsyncBoxBody<Bytes, Infallible>;
+
+/// Serve the plain HTTP endpoints on `M17WEB_PROXY_HTTP_LISTENER`.
+pub async fn serve(address: String) {
+    let listener = match TcpListener::bind(&address).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("HTTP: Failed to listen on {}: {}", address, e);
+            return;
+        }
+    };
+    info!("HTTP: Listening on {}", address);
+
+    loop {
+        let (stream, remote) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                error!("HTTP: Accept failed: {}", e);
+                continue;
+            }
+        };
+        tokio::spawn(async move {
+            if let Err(e) = http1::Builder::new().serve_connection(TokioIo::new(stream), service_fn(route)).await {
+                debug!("HTTP: Connection from {} failed: {}", remote, e);
+            }
+        });
+    }
+}
+
+async fn route(request: Request<Incoming>) -> Result<Response<Body>, Infallible> {
+    let path = request.uri().path().to_string();
+    let segments: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+
+    if segments.first() == Some(&"admin") {
+        let authorization = request.headers().get(AUTHORIZATION).and_then(|value| value.to_str().ok());
+        return Ok(if !admin::enabled() {
+            text_response(StatusCode::NOT_FOUND, "Not found")
+        } else if !admin::authorized(authorization) {
+            let mut response = text_response(StatusCode::UNAUTHORIZED, "Unauthorized");
+            response.headers_mut().insert(WWW_AUTHENTICATE, "Bearer".parse().unwrap());
+            response
+        } else {
+            admin_route(request.method(), &segments[1..]).await
+        });
+    }
+
+    let response = match (request.method(), segments.as_slice()) {
+        (&Method::GET, ["listen", reflector, file]) => listen(reflector, file).await,
+        (&Method::GET, ["healthz"]) => json_response(StatusCode::OK, r#"{"status":"ok"}"#.to_string()),
+        (&Method::GET, ["readyz"]) => {
+            let (ready, body) = health::readiness().await;
+            let status = if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+            json_response(status, body)
+        }
+        (&Method::GET, ["metrics"]) => Response::builder()
+            .header(CONTENT_TYPE, "text/plain; version=0.0.4; charset=utf-8")
+            .body(Full::new(Bytes::from(metrics::render().await)).boxed_unsync())
+            .unwrap(),
+        _ => text_response(StatusCode::NOT_FOUND, "Not found"),
+    };
+    Ok(response)
+}
+
+async fn admin_route(method: &Method, segments: &[&str]) -> Response<Body> {
+    match (method, segments) {
+        (&Method::GET, ["modules"]) => json_response(StatusCode::OK, admin::modules().await),
+        (&Method::GET, ["sessions"]) => json_response(StatusCode::OK, admin::sessions().await),
+        (&Method::PUT, ["modules", reflector, module]) => {
+            let (reflector, module) = match admin::parse_module(reflector, module) {
+                Ok(parsed) => parsed,
+                Err(e) => return text_response(StatusCode::BAD_REQUEST, &e),
+            };
+            match admin::add_module(&reflector, &module, false).await {
+                Ok(true) => text_response(StatusCode::CREATED, "Added"),
+                Ok(false) => text_response(StatusCode::OK, "Already subscribed"),
+                Err(e) => {
+                    error!("Admin: Failed to add {} Module {}: {}", reflector, module, e);
+                    text_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to open a socket")
+                }
+            }
+        }
+        (&Method::DELETE, ["modules", reflector, module]) => {
+            let (reflector, module) = match admin::parse_module(reflector, module) {
+                Ok(parsed) => parsed,
+                Err(e) => return text_response(StatusCode::BAD_REQUEST, &e),
+            };
+            if admin::remove_module(&reflector, &module).await {
+                text_response(StatusCode::OK, "Removed")
+            } else {
+                text_response(StatusCode::NOT_FOUND, "Unknown reflector or module")
+            }
+        }
+        _ => text_response(StatusCode::NOT_FOUND, "Not found"),
+    }
+}
+
+#[cfg(feature = "transcode")]
+async fn listen(reflector: &str, file: &str) -> Response<Body> {
+    let Some(module) = file.strip_suffix(".ogg") else {
+        return text_response(StatusCode::NOT_FOUND, "Only .ogg streams are available");
+    };
+    match crate::listen::subscribe(reflector, module).await {
+        Some(body) => Response::builder()
+            .header(CONTENT_TYPE, "audio/ogg")
+            .header("Cache-Control", "no-cache, no-store")
+            .body(body)
+            .unwrap(),
+        None => text_response(StatusCode::NOT_FOUND, "Unknown reflector or module"),
+    }
+}
+
+#[cfg(not(feature = "transcode"))]
+async fn listen(_reflector: &str, _file: &str) -> Response<Body> {
+    text_response(StatusCode::NOT_IMPLEMENTED, "Audio streaming requires the transcode feature")
+}
+
+fn json_response(status: StatusCode, body: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Full::new(Bytes::from(body)).boxed_unsync())
+        .unwrap()
+}
+
+pub fn text_response(status: StatusCode, text: &str) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(Full::new(Bytes::from(text.to_string())).boxed_unsync())
+        .unwrap()
+}
@@ -0,0 +1,257 @@
+use std::time::{Duration, Instant};
+
+use log::{error, info, warn};
+use rand::Rng;
+use serde::Serialize;
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::events::publish;
+use crate::payloads::{create_conn_payload, create_disc_payload};
+use crate::qso::{QsoEndReason, QsoMeta};
+use crate::recorder::record_end;
+use crate::store::record_qso;
+use crate::reflector::{spawn_receiver, ReflectorFrame};
+use crate::resolver::resolve;
+use crate::{get_epoch, metrics, ReflectorConnection, CALLSIGN, LINK_TIMEOUT, REFLECTOR_CONNECTIONS};
+
+/// Time to wait for ACKN after the first CONN/LSTN, doubled on every further attempt.
+const ACKN_TIMEOUT: Duration = Duration::from_secs(5);
+/// Upper bound of the retry delay.
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+/// A reflector that answered NACK is not asked again sooner than this.
+const DENIED_BACKOFF: Duration = Duration::from_secs(60);
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkState {
+    /// Looking up the reflector address on the DHT or in the hostfile.
+    Resolving,
+    /// CONN/LSTN sent, waiting for ACKN.
+    Connecting,
+    /// Acknowledged by the reflector and receiving PINGs.
+    Linked,
+    /// The reflector answered NACK.
+    Denied,
+    /// DISC received or the reflector went silent.
+    Disconnected,
+}
+
+impl LinkState {
+    pub const ALL: [LinkState; 5] = [
+        LinkState::Resolving,
+        LinkState::Connecting,
+        LinkState::Linked,
+        LinkState::Denied,
+        LinkState::Disconnected,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LinkState::Resolving => "resolving",
+            LinkState::Connecting => "connecting",
+            LinkState::Linked => "linked",
+            LinkState::Denied => "denied",
+            LinkState::Disconnected => "disconnected",
+        }
+    }
+}
+
+/// Link state of a reflector connection and when to act on it next.
+pub struct Link {
+    pub state: LinkState,
+    /// Attempts since the link was last established, drives the backoff.
+    attempts: u32,
+    next_attempt: Instant,
+}
+
+impl Default for Link {
+    fn default() -> Self {
+        Link {
+            state: LinkState::Resolving,
+            attempts: 0,
+            next_attempt: Instant::now(),
+        }
+    }
+}
+
+impl Link {
+    fn due(&self) -> bool {
+        Instant::now() >= self.next_attempt
+    }
+
+    /// Schedule the next attempt with exponential backoff and ±20% jitter.
+    pub fn backoff(&mut self) {
+        self.backoff_at_least(Duration::ZERO);
+    }
+
+    /// Back off after a NACK, for at least a minute.
+    pub fn denied(&mut self) {
+        self.backoff_at_least(DENIED_BACKOFF);
+    }
+
+    fn backoff_at_least(&mut self, min: Duration) {
+        let delay = ACKN_TIMEOUT.saturating_mul(1 << self.attempts.min(16)).min(MAX_BACKOFF);
+        let delay = delay.mul_f64(rand::rng().random_range(0.8..1.2)).max(min);
+        self.next_attempt = Instant::now() + delay;
+        self.attempts += 1;
+    }
+
+    /// Act on the link at the next housekeeping tick.
+    fn reset(&mut self) {
+        self.attempts = 0;
+        self.next_attempt = Instant::now();
+    }
+}
+
+impl ReflectorConnection {
+    /// Switch the link state, notifying info clients on change.
+    pub async fn set_link_state(&mut self, state: LinkState) {
+        if state == LinkState::Linked {
+            self.link.attempts = 0;
+        }
+        if self.link.state != state {
+            info!("{} Module {}: {:?} -> {:?}", self.reflector, self.module, self.link.state, state);
+            self.link.state = state;
+            publish(&self.link_state()).await;
+        }
+    }
+
+    /// Send CONN (or LSTN for listen-only modules) and wait for ACKN.
+    async fn send_conn(&mut self, callsign: &str) {
+        self.set_link_state(LinkState::Connecting).await;
+        let method = if self.transmit { "CONN" } else { "LSTN" };
+        let conn_payload = create_conn_payload(method.to_string(), callsign.to_string(), self.module.clone());
+        if let Err(e) = self.socket.send(&conn_payload).await {
+            error!("Failed to send {} to {}: {}", method, self.reflector, e);
+        }
+        metrics::reconnect(&self.reflector, &self.module);
+        self.link.backoff();
+    }
+
+    /// Leave the reflector for good: end ongoing QSOs, stop receiving and send DISC.
+    /// Returns the QSOs that were ended.
+    pub async fn unlink(&mut self, reason: QsoEndReason) -> Vec<QsoMeta> {
+        let qsos = self.qsos.end_all();
+        self.text_assembler.clear();
+        for qso in qsos.iter() {
+            record_qso(qso.to_record(&self.reflector, &self.module));
+            record_end(&self.reflector, &self.module, qso, reason);
+        }
+
+        if let Some(receiver) = self.receiver.take() {
+            receiver.abort();
+        }
+        if matches!(self.link.state, LinkState::Connecting | LinkState::Linked) {
+            info!("Sending DISC to {} Module {}", self.reflector, self.module);
+            if let Err(e) = self.socket.send(&create_disc_payload(CALLSIGN.clone())).await {
+                error!("Failed to send DISC to {}: {}", self.reflector, e);
+            }
+        }
+        self.link.state = LinkState::Disconnected;
+        qsos
+    }
+}
+
+/// Drive the link state of all connections: resolve, (re)connect on backoff,
+/// and detect missing ACKNs and PINGs.
+pub async fn handle_links(callsign: &str, frame_tx: &UnboundedSender<ReflectorFrame>) {
+    let now = get_epoch().as_secs();
+    let mut resolving: Vec<String> = vec![];
+
+    for reflector_connection in REFLECTOR_CONNECTIONS.lock().await.iter_mut() {
+        match reflector_connection.link.state {
+            LinkState::Resolving => {
+                if reflector_connection.link.due() {
+                    reflector_connection.link.backoff();
+                    if !resolving.contains(&reflector_connection.reflector) {
+                        resolving.push(reflector_connection.reflector.clone());
+                    }
+                }
+            }
+            LinkState::Linked => {
+                if now.saturating_sub(reflector_connection.last_heard) > LINK_TIMEOUT {
+                    warn!("No PING from {} Module {} for {} seconds, relinking", reflector_connection.reflector, reflector_connection.module, LINK_TIMEOUT);
+                    reflector_connection.set_link_state(LinkState::Disconnected).await;
+                    reflector_connection.link.reset();
+                    reflector_connection.send_conn(callsign).await;
+                }
+            }
+            state => {
+                if reflector_connection.link.due() {
+                    if state == LinkState::Connecting && reflector_connection.link.attempts > 0 {
+                        warn!("No ACKN from {} Module {}, retrying", reflector_connection.reflector, reflector_connection.module);
+                    }
+                    reflector_connection.send_conn(callsign).await;
+                }
+            }
+        }
+    }
+
+    // DHT lookups take a while, don't hold up the frame handling meanwhile
+    for reflector in resolving {
+        tokio::spawn(resolve_link(reflector, frame_tx.clone()));
+    }
+}
+
+/// Look up a reflector and point its resolving connections at the address.
+async fn resolve_link(reflector: String, frame_tx: UnboundedSender<ReflectorFrame>) {
+    let Some(address) = resolve(&reflector).await else {
+        return;
+    };
+
+    for reflector_connection in REFLECTOR_CONNECTIONS.lock().await.iter_mut()
+        .filter(|c| c.reflector == reflector && c.link.state == LinkState::Resolving) {
+        if let Err(e) = reflector_connection.socket.connect(&address).await {
+            error!("Failed to connect to {} at {}: {}", reflector, address, e);
+            continue;
+        }
+        reflector_connection.address = address.clone();
+        if reflector_connection.receiver.is_none() {
+            reflector_connection.receiver = Some(spawn_receiver(
+                reflector_connection.reflector.clone(),
+                reflector_connection.module.clone(),
+                reflector_connection.socket.clone(),
+                frame_tx.clone(),
+            ));
+        }
+        reflector_connection.link.reset();
+        reflector_connection.set_link_state(LinkState::Connecting).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Delay until the next attempt, measured right after scheduling it
+    fn delay(link: &Link) -> Duration {
+        link.next_attempt.saturating_duration_since(Instant::now())
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_cap() {
+        let mut link = Link::default();
+        assert!(link.due());
+
+        for expected in [5, 10, 20, 40, 80, 160, 300, 300] {
+            link.backoff();
+            let delay = delay(&link).as_secs_f64();
+            assert!(!link.due());
+            assert!(delay <= expected as f64 * 1.2 && delay >= expected as f64 * 0.8 - 0.1, "{} not around {}", delay, expected);
+        }
+    }
+
+    #[test]
+    fn denied_and_reset() {
+        let mut link = Link::default();
+        link.denied();
+        assert!(delay(&link) >= DENIED_BACKOFF - Duration::from_millis(100));
+        assert_eq!(link.attempts, 1);
+
+        link.reset();
+        assert!(link.due());
+        assert_eq!(link.attempts, 0);
+        link.backoff();
+        assert!(delay(&link) <= ACKN_TIMEOUT.mul_f64(1.2));
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::{HashMap, VecDeque};
+use std::convert::Infallible;
+use std::time::Duration;
+
+use bytes::Bytes;
+use http_body_util::{BodyExt, StreamBody};
+use hyper::body::Frame;
+use lazy_static::lazy_static;
+use log::{info, warn};
+use ogg::{PacketWriteEndInfo, PacketWriter};
+use rand::Rng;
+use tokio::sync::{mpsc, Mutex};
+
+use crate::http::Body;
+use crate::transcode::{Transcoder, SAMPLES_PER_FRAME, SAMPLE_RATE};
+use crate::{metrics, REFLECTOR_CONNECTIONS};
+
+lazy_static! {
+    static ref STREAMS: Mutex<HashMap<(String, String), ModuleStream>> = Mutex::new(HashMap::new());
+}
+
+/// Opus granule positions always count 48 kHz samples.
+const GRANULE_PER_FRAME: u64 = (SAMPLES_PER_FRAME as u64) * 48000 / SAMPLE_RATE as u64;
+/// Encoder delay announced in the Opus header, in 48 kHz samples.
+const PRE_SKIP: u16 = 312;
+/// Opus packets per Ogg page, 200 ms of audio.
+const PAGE_PACKETS: usize = 10;
+/// Pages queued for a listener before it is considered too slow and dropped.
+const LISTENER_QUEUE: usize = 50;
+/// Audio buffered at the start of a QSO to ride out network jitter, three M17 frames.
+const PREBUFFER_SAMPLES: usize = 3 * 2 * SAMPLES_PER_FRAME;
+/// Buffered audio beyond one second is dropped to keep the latency bounded.
+const MAX_BUFFER_SAMPLES: usize = SAMPLE_RATE as usize;
+
+/// Decoded audio of a module waiting to be encoded, and the HTTP listeners it goes to.
+#[derive(Default)]
+struct ModuleStream {
+    buffer: VecDeque<i16>,
+    /// Set while waiting for the prebuffer to fill, silence is sent meanwhile.
+    buffering: bool,
+    listeners: Vec<Listener>,
+}
+
+/// An HTTP client receiving an Ogg/Opus stream.
+struct Listener {
+    serial: u32,
+    writer: PacketWriter<Vec<u8>>,
+    granule: u64,
+    packets: usize,
+    tx: mpsc::Sender<Bytes>,
+}
+
+impl Listener {
+    fn new(reflector: &str, module: &str, tx: mpsc::Sender<Bytes>) -> Listener {
+        let mut listener = Listener {
+            serial: rand::rng().random(),
+            writer: PacketWriter::new(vec![]),
+            granule: 0,
+            packets: 0,
+            tx,
+        };
+
+        let mut head = b"OpusHead".to_vec();
+        head.push(1); // Version
+        head.push(1); // Channels
+        head.extend_from_slice(&PRE_SKIP.to_le_bytes());
+        head.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+        head.extend_from_slice(&0i16.to_le_bytes()); // Output gain
+        head.push(0); // Channel mapping family
+
+        let vendor = concat!("m17web-proxy ", env!("CARGO_PKG_VERSION"));
+        let title = format!("TITLE={} Module {}", reflector, module);
+        let mut tags = b"OpusTags".to_vec();
+        tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+        tags.extend_from_slice(vendor.as_bytes());
+        tags.extend_from_slice(&1u32.to_le_bytes());
+        tags.extend_from_slice(&(title.len() as u32).to_le_bytes());
+        tags.extend_from_slice(title.as_bytes());
+
+        // Header packets have their own pages, writing into a Vec cannot fail
+        let _ = listener.writer.write_packet(head.into_boxed_slice(), listener.serial, PacketWriteEndInfo::EndPage, 0);
+        let _ = listener.writer.write_packet(tags.into_boxed_slice(), listener.serial, PacketWriteEndInfo::EndPage, 0);
+        listener
+    }
+
+    /// Add an Opus packet, sending the page once it is full.
+    /// Returns false if the listener is gone or cannot keep up.
+    fn write(&mut self, packet: &[u8]) -> bool {
+        self.granule += GRANULE_PER_FRAME;
+        self.packets += 1;
+        let end = if self.packets >= PAGE_PACKETS {
+            self.packets = 0;
+            PacketWriteEndInfo::EndPage
+        } else {
+            PacketWriteEndInfo::NormalPacket
+        };
+        let _ = self.writer.write_packet(packet.to_vec().into_boxed_slice(), self.serial, end, self.granule);
+        self.flush()
+    }
+
+    fn flush(&mut self) -> bool {
+        if self.writer.inner().is_empty() {
+            return !self.tx.is_closed();
+        }
+        let page = std::mem::take(self.writer.inner_mut());
+        metrics::bytes_sent("listen", page.len());
+        self.tx.try_send(Bytes::from(page)).is_ok()
+    }
+}
+
+/// Whether any HTTP client listens to the module.
+pub async fn is_listened(reflector: &str, module: &str) -> bool {
+    STREAMS.lock().await.contains_key(&(reflector.to_string(), module.to_string()))
+}
+
+/// Queue decoded audio of a stream frame for the module's listeners.
+pub async fn push_audio(reflector: &str, module: &str, pcm: &[i16]) {
+    if let Some(stream) = STREAMS.lock().await.get_mut(&(reflector.to_string(), module.to_string())) {
+        stream.buffer.extend(pcm);
+        let excess = stream.buffer.len().saturating_sub(MAX_BUFFER_SAMPLES);
+        stream.buffer.drain(..excess);
+    }
+}
+
+/// Start an Ogg/Opus stream of a module. Returns None if the module is not linked.
+pub async fn subscribe(reflector: &str, module: &str) -> Option<Body> {
+    let linked = REFLECTOR_CONNECTIONS.lock().await.iter()
+        .any(|c| c.reflector == reflector && c.module == module);
+    if !linked {
+        return None;
+    }
+
+    let (tx, rx) = mpsc::channel(LISTENER_QUEUE);
+    let mut listener = Listener::new(reflector, module, tx);
+    listener.flush();
+
+    let key = (reflector.to_string(), module.to_string());
+    let mut streams = STREAMS.lock().await;
+    if !streams.contains_key(&key) {
+        spawn_encoder(key.clone());
+    }
+    let stream = streams.entry(key).or_insert_with(|| ModuleStream { buffering: true, ..Default::default() });
+    stream.listeners.push(listener);
+    info!("HTTP listener attached to {} Module {} ({} listening)", reflector, module, stream.listeners.len());
+
+    let pages = futures_util::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|page| (Ok::<_, Infallible>(Frame::data(page)), rx))
+    });
+    Some(StreamBody::new(pages).boxed_unsync())
+}
+
+/// End the streams of a module that was removed.
+pub async fn close(reflector: &str, module: &str) {
+    if let Some(stream) = STREAMS.lock().await.remove(&(reflector.to_string(), module.to_string())) {
+        info!("Closing {} HTTP listeners of removed {} Module {}", stream.listeners.len(), reflector, module);
+    }
+}
+
+/// Encode the module's audio in real time, 20 ms per tick, filling gaps between QSOs with silence.
+/// Stops once the last listener is gone.
+fn spawn_encoder(key: (String, String)) {
+    tokio::spawn(async move {
+        let mut transcoder = Transcoder::default();
+        let mut clock = tokio::time::interval(Duration::from_millis(20));
+        loop {
+            clock.tick().await;
+
+            let mut streams = STREAMS.lock().await;
+            let Some(stream) = streams.get_mut(&key) else {
+                return;
+            };
+
+            if stream.buffering && stream.buffer.len() >= PREBUFFER_SAMPLES {
+                stream.buffering = false;
+            }
+            let pcm: Vec<i16> = if stream.buffering || stream.buffer.len() < SAMPLES_PER_FRAME {
+                stream.buffering = true;
+                vec![0; SAMPLES_PER_FRAME]
+            } else {
+                stream.buffer.drain(..SAMPLES_PER_FRAME).collect()
+            };
+
+            // Also drop disconnected listeners when the encoder produces no output
+            stream.listeners.retain(|listener| !listener.tx.is_closed());
+            for packet in transcoder.encode_opus(&pcm) {
+                stream.listeners.retain_mut(|listener| {
+                    let alive = listener.write(&packet);
+                    if !alive && !listener.tx.is_closed() {
+                        warn!("Dropping slow HTTP listener of {} Module {}", key.0, key.1);
+                    }
+                    alive
+                });
+            }
+
+            if stream.listeners.is_empty() {
+                info!("Last HTTP listener of {} Module {} left", key.0, key.1);
+                streams.remove(&key);
+                return;
+            }
+        }
+    });
+}
@@ -0,0 +1,467 @@
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::meta::GnssPosition;
+use crate::utils::{crc16, decode_callsign};
+
+/// Length of the Link Setup Data (LSF without CRC) as carried in M17 IP frames.
+pub const LSD_LEN: usize = 28;
+/// Length of an "M17P" packet without payload: magic, LSF including CRC.
+pub const PACKET_HEADER_LEN: usize = 34;
+/// Length of an "M17 " stream frame: magic, stream ID, LSD, frame number, payload, CRC.
+pub const STREAM_FRAME_LEN: usize = 54;
+
+/// Packet/stream indicator (TYPE bit 0).
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Mode {
+    Packet,
+    Stream,
+}
+
+/// Data type (TYPE bits 1-2).
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DataType {
+    Reserved,
+    Data,
+    Voice,
+    VoiceData,
+}
+
+/// Encryption type (TYPE bits 3-4).
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum EncryptionType {
+    None,
+    Scrambler,
+    Aes,
+    Other,
+}
+
+/// Contents of the META field, signalled by the encryption subtype when unencrypted.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MetaType {
+    Text,
+    Gnss,
+    ExtendedCallsign,
+    Reserved,
+}
+
+/// Raw TYPE field for an unencrypted data packet on the given CAN.
+pub fn data_packet_type(can: u8) -> u16 {
+    (0x01 << 1) | (((can & 0x0F) as u16) << 7)
+}
+
+/// Raw TYPE field for an unencrypted Codec2 3200 voice stream on the given CAN.
+pub fn voice_stream_type(can: u8) -> u16 {
+    0x0001 | (0x02 << 1) | (((can & 0x0F) as u16) << 7)
+}
+
+/// Decoded 16-bit TYPE field of the LSF, in the layout of the M17 specification v1.0.
+#[derive(Serialize, Clone, Debug)]
+pub struct LsfType {
+    pub raw: u16,
+    pub mode: Mode,
+    pub data_type: DataType,
+    pub encryption_type: EncryptionType,
+    pub encryption_subtype: u8,
+    pub can: u8,
+}
+
+impl LsfType {
+    pub fn from_raw(raw: u16) -> LsfType {
+        LsfType {
+            raw,
+            mode: if raw & 0x0001 != 0 { Mode::Stream } else { Mode::Packet },
+            data_type: match (raw >> 1) & 0x03 {
+                1 => DataType::Data,
+                2 => DataType::Voice,
+                3 => DataType::VoiceData,
+                _ => DataType::Reserved,
+            },
+            encryption_type: match (raw >> 3) & 0x03 {
+                1 => EncryptionType::Scrambler,
+                2 => EncryptionType::Aes,
+                3 => EncryptionType::Other,
+                _ => EncryptionType::None,
+            },
+            encryption_subtype: ((raw >> 5) & 0x03) as u8,
+            can: ((raw >> 7) & 0x0F) as u8,
+        }
+    }
+
+    /// What the META field carries, or `None` if it is used as encryption nonce/IV.
+    pub fn meta_type(&self) -> Option<MetaType> {
+        if self.encryption_type != EncryptionType::None {
+            return None;
+        }
+        Some(match self.encryption_subtype {
+            0 => MetaType::Text,
+            1 => MetaType::Gnss,
+            2 => MetaType::ExtendedCallsign,
+            _ => MetaType::Reserved,
+        })
+    }
+}
+
+/// Link Setup Frame contents: destination, source, TYPE and META.
+#[derive(Serialize, Clone, Debug)]
+pub struct Lsf {
+    pub dst: String,
+    pub src: String,
+    #[serde(rename = "type")]
+    pub lsf_type: LsfType,
+    pub meta: Vec<u8>,
+}
+
+impl Lsf {
+    /// Parse the 28 byte link setup data (DST, SRC, TYPE, META).
+    pub fn parse(lsd: &[u8]) -> Option<Lsf> {
+        if lsd.len() < LSD_LEN {
+            return None;
+        }
+        Some(Lsf {
+            dst: decode_callsign(&lsd[0..6]),
+            src: decode_callsign(&lsd[6..12]),
+            lsf_type: LsfType::from_raw(u16::from_be_bytes([lsd[12], lsd[13]])),
+            meta: lsd[14..28].to_vec(),
+        })
+    }
+
+    /// Whether the META field carries a text-data block.
+    pub fn has_text(&self) -> bool {
+        self.lsf_type.meta_type() == Some(MetaType::Text)
+    }
+
+    /// Decode the META field as a GNSS position, if the TYPE field says it carries one.
+    pub fn gnss(&self) -> Option<GnssPosition> {
+        match self.lsf_type.meta_type() {
+            Some(MetaType::Gnss) => GnssPosition::decode(&self.meta),
+            _ => None,
+        }
+    }
+}
+
+/// A single "M17 " stream frame as relayed by a reflector.
+#[derive(Clone, Debug)]
+pub struct StreamFrame {
+    pub stream_id: u16,
+    pub lsf: Lsf,
+    /// Frame counter with the end-of-stream bit masked off.
+    pub frame_number: u16,
+    /// End-of-stream bit (MSB of the frame number).
+    pub last: bool,
+    pub payload: Vec<u8>,
+}
+
+impl StreamFrame {
+    /// Parse and CRC-check an "M17 " datagram. The CRC covers everything before it.
+    pub fn parse(buf: &[u8]) -> Result<StreamFrame, FrameError> {
+        if buf.len() < STREAM_FRAME_LEN {
+            return Err(FrameError::TooShort(buf.len()));
+        }
+        if &buf[..4] != b"M17 " {
+            return Err(FrameError::BadMagic);
+        }
+
+        let expected = u16::from_be_bytes([buf[52], buf[53]]);
+        let actual = crc16(&buf[..52]);
+        if expected != actual {
+            return Err(FrameError::Crc { expected, actual });
+        }
+
+        let frame_number = u16::from_be_bytes([buf[34], buf[35]]);
+        Ok(StreamFrame {
+            stream_id: u16::from_be_bytes([buf[4], buf[5]]),
+            lsf: Lsf::parse(&buf[6..34]).ok_or(FrameError::TooShort(buf.len()))?,
+            frame_number: frame_number & 0x7FFF,
+            last: frame_number & 0x8000 != 0,
+            payload: buf[36..52].to_vec(),
+        })
+    }
+}
+
+/// Protocol identifier, the first byte of a packet payload.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PacketProtocol {
+    Raw,
+    Ax25,
+    Aprs,
+    SixLowPan,
+    Ipv4,
+    Sms,
+    Winlink,
+    Tle,
+    Unknown,
+}
+
+impl PacketProtocol {
+    pub fn from_id(id: u8) -> PacketProtocol {
+        match id {
+            0x00 => PacketProtocol::Raw,
+            0x01 => PacketProtocol::Ax25,
+            0x02 => PacketProtocol::Aprs,
+            0x03 => PacketProtocol::SixLowPan,
+            0x04 => PacketProtocol::Ipv4,
+            0x05 => PacketProtocol::Sms,
+            0x06 => PacketProtocol::Winlink,
+            0x07 => PacketProtocol::Tle,
+            _ => PacketProtocol::Unknown,
+        }
+    }
+
+    /// Name as used in JSON events and the store.
+    pub fn name(&self) -> &'static str {
+        match self {
+            PacketProtocol::Raw => "raw",
+            PacketProtocol::Ax25 => "ax25",
+            PacketProtocol::Aprs => "aprs",
+            PacketProtocol::SixLowPan => "six_low_pan",
+            PacketProtocol::Ipv4 => "ipv4",
+            PacketProtocol::Sms => "sms",
+            PacketProtocol::Winlink => "winlink",
+            PacketProtocol::Tle => "tle",
+            PacketProtocol::Unknown => "unknown",
+        }
+    }
+
+    pub fn id(&self) -> u8 {
+        match self {
+            PacketProtocol::Raw => 0x00,
+            PacketProtocol::Ax25 => 0x01,
+            PacketProtocol::Aprs => 0x02,
+            PacketProtocol::SixLowPan => 0x03,
+            PacketProtocol::Ipv4 => 0x04,
+            PacketProtocol::Sms => 0x05,
+            PacketProtocol::Winlink => 0x06,
+            PacketProtocol::Tle => 0x07,
+            PacketProtocol::Unknown => 0xFF,
+        }
+    }
+}
+
+/// Reasons a stream frame or packet is rejected.
+#[derive(Debug)]
+pub enum FrameError {
+    TooShort(usize),
+    BadMagic,
+    Crc { expected: u16, actual: u16 },
+    LsfCrc { expected: u16, actual: u16 },
+    PayloadCrc { expected: u16, actual: u16 },
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FrameError::TooShort(len) => write!(f, "frame too short ({} bytes)", len),
+            FrameError::BadMagic => write!(f, "unexpected magic"),
+            FrameError::Crc { expected, actual } => {
+                write!(f, "CRC mismatch (expected {:04x}, got {:04x})", expected, actual)
+            }
+            FrameError::LsfCrc { expected, actual } => {
+                write!(f, "LSF CRC mismatch (expected {:04x}, got {:04x})", expected, actual)
+            }
+            FrameError::PayloadCrc { expected, actual } => {
+                write!(f, "payload CRC mismatch (expected {:04x}, got {:04x})", expected, actual)
+            }
+        }
+    }
+}
+
+/// A single "M17P" packet-mode frame as relayed by a reflector.
+#[derive(Clone, Debug)]
+pub struct PacketFrame {
+    pub lsf: Lsf,
+    pub protocol: PacketProtocol,
+    pub protocol_id: u8,
+    /// Packet data without protocol byte and CRC.
+    pub data: Vec<u8>,
+}
+
+impl PacketFrame {
+    /// Parse and CRC-check a complete "M17P" datagram:
+    /// magic, LSD, LSF CRC, protocol byte, data, payload CRC.
+    pub fn parse(buf: &[u8]) -> Result<PacketFrame, FrameError> {
+        // Protocol byte and payload CRC are mandatory
+        if buf.len() < PACKET_HEADER_LEN + 3 {
+            return Err(FrameError::TooShort(buf.len()));
+        }
+        if &buf[..4] != b"M17P" {
+            return Err(FrameError::BadMagic);
+        }
+
+        let lsd = &buf[4..4 + LSD_LEN];
+        let expected = u16::from_be_bytes([buf[32], buf[33]]);
+        let actual = crc16(lsd);
+        if expected != actual {
+            return Err(FrameError::LsfCrc { expected, actual });
+        }
+
+        let payload = &buf[PACKET_HEADER_LEN..buf.len() - 2];
+        let expected = u16::from_be_bytes([buf[buf.len() - 2], buf[buf.len() - 1]]);
+        let actual = crc16(payload);
+        if expected != actual {
+            return Err(FrameError::PayloadCrc { expected, actual });
+        }
+
+        Ok(PacketFrame {
+            lsf: Lsf::parse(lsd).ok_or(FrameError::TooShort(buf.len()))?,
+            protocol: PacketProtocol::from_id(payload[0]),
+            protocol_id: payload[0],
+            data: payload[1..].to_vec(),
+        })
+    }
+
+    /// The text of an SMS packet, up to its NUL terminator.
+    /// Returns `None` for other protocols or if the text is not valid UTF-8.
+    pub fn sms_text(&self) -> Option<String> {
+        if self.protocol != PacketProtocol::Sms {
+            return None;
+        }
+        let end = self.data.iter().position(|&b| b == 0).unwrap_or(self.data.len());
+        std::str::from_utf8(&self.data[..end]).ok().map(|text| text.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::payloads::{create_lsd, create_packet_payload, create_stream_payload};
+
+    #[test]
+    fn crc16_vectors() {
+        assert_eq!(crc16(b""), 0xFFFF);
+        assert_eq!(crc16(b"A"), 0x206E);
+        assert_eq!(crc16(b"123456789"), 0x772B);
+    }
+
+    #[test]
+    fn lsf_broadcast_and_invalid_callsigns() {
+        let mut lsd = [0xFF; LSD_LEN];
+        lsd[6..12].copy_from_slice(&[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFE]);
+        let lsf = Lsf::parse(&lsd).unwrap();
+        assert_eq!(lsf.dst, "ALL");
+        // Above 40^9 but not broadcast
+        assert_eq!(lsf.src, "");
+    }
+
+    #[test]
+    fn lsd_broadcast_destination() {
+        let lsd = create_lsd("ALL".to_string(), "M17XYZ".to_string(), voice_stream_type(0), &[]);
+        assert_eq!(lsd[..6], [0xFF; 6]);
+        assert_eq!(Lsf::parse(&lsd).unwrap().dst, "ALL");
+    }
+
+    #[test]
+    fn lsf_type_bits() {
+        let voice = LsfType::from_raw(voice_stream_type(5));
+        assert_eq!(voice.mode, Mode::Stream);
+        assert_eq!(voice.data_type, DataType::Voice);
+        assert_eq!(voice.encryption_type, EncryptionType::None);
+        assert_eq!(voice.can, 5);
+        assert_eq!(voice.meta_type(), Some(MetaType::Text));
+
+        let data = LsfType::from_raw(data_packet_type(15));
+        assert_eq!(data.mode, Mode::Packet);
+        assert_eq!(data.data_type, DataType::Data);
+        assert_eq!(data.can, 15);
+
+        let gnss = LsfType::from_raw(voice_stream_type(0) | (1 << 5));
+        assert_eq!(gnss.encryption_subtype, 1);
+        assert_eq!(gnss.meta_type(), Some(MetaType::Gnss));
+
+        let aes = LsfType::from_raw(voice_stream_type(0) | (2 << 3) | (1 << 5));
+        assert_eq!(aes.encryption_type, EncryptionType::Aes);
+        assert_eq!(aes.meta_type(), None);
+    }
+
+    #[test]
+    fn stream_frame_round_trip() {
+        let lsd = create_lsd("N0CALL".to_string(), "M17XYZ".to_string(), voice_stream_type(3), b"\x11Hi");
+        let buf = create_stream_payload(0x1234, &lsd, 7, true, &[0xAA; 16]);
+        assert_eq!(buf.len(), STREAM_FRAME_LEN);
+
+        let frame = StreamFrame::parse(&buf).unwrap();
+        assert_eq!(frame.stream_id, 0x1234);
+        assert_eq!(frame.frame_number, 7);
+        assert!(frame.last);
+        assert_eq!(frame.payload, vec![0xAA; 16]);
+        assert_eq!(frame.lsf.dst, "N0CALL");
+        assert_eq!(frame.lsf.src, "M17XYZ");
+        assert_eq!(frame.lsf.lsf_type.can, 3);
+        assert_eq!(&frame.lsf.meta[..3], b"\x11Hi");
+    }
+
+    #[test]
+    fn stream_frame_errors() {
+        let lsd = create_lsd("N0CALL".to_string(), "M17XYZ".to_string(), voice_stream_type(0), &[]);
+        let mut buf = create_stream_payload(1, &lsd, 0, false, &[0; 16]);
+
+        assert!(matches!(StreamFrame::parse(&[]), Err(FrameError::TooShort(0))));
+        assert!(matches!(StreamFrame::parse(&buf[..20]), Err(FrameError::TooShort(20))));
+
+        let mut wrong_magic = buf.clone();
+        wrong_magic[3] = b'P';
+        assert!(matches!(StreamFrame::parse(&wrong_magic), Err(FrameError::BadMagic)));
+
+        buf[40] ^= 0x01;
+        assert!(matches!(StreamFrame::parse(&buf), Err(FrameError::Crc { .. })));
+    }
+
+    #[test]
+    fn packet_frame_round_trip() {
+        let lsd = create_lsd("ALL".to_string(), "M17XYZ".to_string(), data_packet_type(0), &[]);
+        let buf = create_packet_payload(&lsd, PacketProtocol::Sms.id(), b"Hello\0");
+
+        let packet = PacketFrame::parse(&buf).unwrap();
+        assert_eq!(packet.protocol, PacketProtocol::Sms);
+        assert_eq!(packet.lsf.dst, "ALL");
+        assert_eq!(packet.lsf.src, "M17XYZ");
+        assert_eq!(packet.data, b"Hello\0");
+        assert_eq!(packet.sms_text().as_deref(), Some("Hello"));
+    }
+
+    #[test]
+    fn packet_protocols() {
+        let lsd = create_lsd("ALL".to_string(), "M17XYZ".to_string(), data_packet_type(0), &[]);
+
+        let aprs = PacketFrame::parse(&create_packet_payload(&lsd, PacketProtocol::Aprs.id(), b"Hello\0")).unwrap();
+        assert_eq!(aprs.protocol, PacketProtocol::Aprs);
+        assert_eq!(aprs.sms_text(), None);
+
+        let unknown = PacketFrame::parse(&create_packet_payload(&lsd, 0x42, b"")).unwrap();
+        assert_eq!((unknown.protocol, unknown.protocol_id), (PacketProtocol::Unknown, 0x42));
+        assert!(unknown.data.is_empty());
+
+        // Text without terminator, and invalid UTF-8
+        let sms = PacketFrame::parse(&create_packet_payload(&lsd, PacketProtocol::Sms.id(), b"Hi")).unwrap();
+        assert_eq!(sms.sms_text().as_deref(), Some("Hi"));
+        let sms = PacketFrame::parse(&create_packet_payload(&lsd, PacketProtocol::Sms.id(), b"\xFF\xFE\0")).unwrap();
+        assert_eq!(sms.sms_text(), None);
+    }
+
+    #[test]
+    fn packet_frame_errors() {
+        let lsd = create_lsd("ALL".to_string(), "M17XYZ".to_string(), data_packet_type(0), &[]);
+        let buf = create_packet_payload(&lsd, PacketProtocol::Raw.id(), b"data");
+
+        assert!(matches!(PacketFrame::parse(&[]), Err(FrameError::TooShort(0))));
+        assert!(matches!(PacketFrame::parse(&buf[..PACKET_HEADER_LEN]), Err(FrameError::TooShort(_))));
+
+        let mut wrong_magic = buf.clone();
+        wrong_magic[3] = b' ';
+        assert!(matches!(PacketFrame::parse(&wrong_magic), Err(FrameError::BadMagic)));
+
+        let mut lsf_corrupt = buf.clone();
+        lsf_corrupt[10] ^= 0x01;
+        assert!(matches!(PacketFrame::parse(&lsf_corrupt), Err(FrameError::LsfCrc { .. })));
+
+        let mut payload_corrupt = buf.clone();
+        payload_corrupt[PACKET_HEADER_LEN + 1] ^= 0x01;
+        assert!(matches!(PacketFrame::parse(&payload_corrupt), Err(FrameError::PayloadCrc { .. })));
+    }
+}
@@ -1,26 +1,65 @@
 
+mod admin;
 mod config;
 mod dht;
+mod health;
+mod events;
 mod hostfile;
+mod http;
+mod link;
+#[cfg(feature = "transcode")]
+mod listen;
+mod m17;
+mod messages;
+mod ondemand;
+mod meta;
+mod metrics;
 mod websocket;
 mod utils;
 mod payloads;
+mod qso;
+mod recorder;
+mod reflector;
+mod replay;
+mod resolver;
+mod shutdown;
+mod stats;
+mod store;
+mod transcode;
+mod transmit;
 
 use tokio::net::UdpSocket;
+use std::collections::HashMap;
 use std::io;
 use std::str;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use ezsockets::Server;
 
 use log::{debug, error, info, warn};
 
 use crate::config::Config;
-use crate::dht::{DhtNode, get_ref_address_from_dht};
-use crate::hostfile::{fetch_hostfile, resolve_from_hostfile, HostFileCache};
-use crate::payloads::{create_conn_payload, create_pong_payload};
-use crate::utils::decode_callsign;
-use crate::websocket::{M17ClientServer, WS_SESSIONS, WsPayload, ModuleInfo};
-use tokio::sync::Mutex;
+use crate::events::publish;
+use crate::link::{handle_links, Link, LinkState};
+use crate::ondemand::unlink_idle;
+use crate::payloads::create_pong_payload;
+use crate::qso::{QsoEndReason, QsoTracker};
+use crate::recorder::{init_recorder, record_audio, record_end, record_start};
+use crate::reflector::ReflectorFrame;
+use crate::replay::ReplayBuffer;
+use crate::resolver::init_resolver;
+use crate::shutdown::shutdown;
+use crate::stats::LinkStats;
+use crate::store::{init_store, record_message, record_qso, MessageRecord};
+use crate::transcode::{AudioFormat, Transcoder};
+use crate::transmit::tx_enabled;
+use crate::m17::{Lsf, PacketFrame, StreamFrame};
+use crate::messages::{MessageHistory, MessagePage, MsgData};
+use crate::meta::{GnssPosition, TextAssembler};
+use crate::websocket::{send_stream_frame, M17ClientServer, WS_SESSIONS, WsPayload, ModuleInfo, InfoEvent};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
 
 use envconfig::Envconfig;
 use lazy_static::lazy_static;
@@ -30,13 +69,16 @@ use serde::Serialize;
 lazy_static! {
     pub static ref REFLECTOR_CONNECTIONS: Mutex<Vec<ReflectorConnection>> = Mutex::new(vec![]);
     pub static ref CFG: Config = Config::init_from_env().unwrap();
-    pub static ref ACTIVE_MOULES: Mutex<ActiveModules> = Mutex::new(ActiveModules {modules: vec![]});
+    /// Callsign the proxy links with, a random SWL callsign if none is configured.
+    pub static ref CALLSIGN: String = if CFG.callsign == "NONE" {
+        format!("SWL{}",rand::rng().random_range(10000..99999))
+    } else {
+        CFG.callsign.clone()
+    };
 }
 
-#[derive(Serialize)]
-pub struct ActiveModules {
-    pub modules: Vec<ModuleInfo>,
-}
+/// Seconds without PING from a reflector after which the link is considered lost.
+const LINK_TIMEOUT: u64 = 60;
 
 #[derive(Serialize)]
 pub struct ReflectorConnection {
@@ -44,24 +86,78 @@ pub struct ReflectorConnection {
     module: String,
     address: String,
     last_heard: u64,
-    active_qso: bool,
-    active_qso_meta: QsoMeta,
-    messages: Vec<MsgData>,
     #[serde(skip_serializing)]
-    socket: UdpSocket,
+    link: Link,
+    #[serde(skip_serializing)]
+    qsos: QsoTracker,
+    #[serde(skip_serializing)]
+    messages: MessageHistory,
+    positions: HashMap<String, PositionReport>,
+    link_stats: LinkStats,
+    #[serde(skip_serializing)]
+    text_assembler: TextAssembler,
+    #[serde(skip_serializing)]
+    transcoder: Transcoder,
+    #[serde(skip_serializing)]
+    replay: ReplayBuffer,
+    transmit: bool,
+    /// Linked for stream clients, unlinked again once nobody subscribes to it.
+    on_demand: bool,
+    #[serde(skip_serializing)]
+    idle_since: Option<Instant>,
+    #[serde(skip_serializing)]
+    socket: Arc<UdpSocket>,
+    /// Receiver task, spawned once the address is resolved.
+    #[serde(skip_serializing)]
+    receiver: Option<JoinHandle<()>>,
 }
 
 #[derive(Serialize, Clone, Debug)]
-pub struct MsgData {
-    callsign: String,
-    message: String,
+pub struct PositionReport {
+    position: GnssPosition,
     timestamp: u64
 }
 
-#[derive(Serialize)]
-pub struct QsoMeta {
-    callsign: String,
-    timestamp: u64
+impl ReflectorConnection {
+    /// A new connection to a reflector module, linked once its address is resolved.
+    async fn new(reflector: &str, module: &str) -> io::Result<ReflectorConnection> {
+        let mut transmit = tx_enabled(reflector, module);
+        if transmit && CFG.callsign == "NONE" {
+            warn!("Transmitting on {} Module {} requires M17WEB_PROXY_CALLSIGN - linking listen-only", reflector, module);
+            transmit = false;
+        }
+
+        Ok(ReflectorConnection {
+            reflector: reflector.to_string(),
+            module: module.to_string(),
+            address: String::new(),
+            last_heard: 0,
+            link: Link::default(),
+            qsos: QsoTracker::default(),
+            messages: MessageHistory::default(),
+            positions: HashMap::new(),
+            link_stats: LinkStats::default(),
+            text_assembler: TextAssembler::default(),
+            transcoder: Transcoder::default(),
+            replay: ReplayBuffer::default(),
+            transmit,
+            on_demand: false,
+            idle_since: None,
+            socket: Arc::new(UdpSocket::bind("0.0.0.0:0").await?),
+            receiver: None,
+        })
+    }
+
+    fn link_state(&self) -> InfoEvent {
+        InfoEvent::LinkState {
+            reflector: self.reflector.clone(),
+            module: self.module.clone(),
+            linked: self.link.state == LinkState::Linked,
+            state: self.link.state,
+            last_heard: self.last_heard,
+            link_stats: self.link_stats.clone(),
+        }
+    }
 }
 
 #[tokio::main]
@@ -70,15 +166,17 @@ async fn main() -> io::Result<()> {
     // Initialize logging
     env_logger::init();
 
+    // Open the persistent store, if configured
+    init_store(&CFG.database).map_err(io::Error::other)?;
+
+    // Start recording QSOs to disk, if configured
+    init_recorder().map_err(io::Error::other)?;
+
     // WS Server instance
     let (server, _) = Server::create(|_server| M17ClientServer {});
     let listener = CFG.ws_listener_address.clone();
 
-    let callsign = if CFG.callsign == "NONE" {
-        format!("SWL{}",rand::rng().random_range(10000..99999))
-    } else {
-        CFG.callsign.clone()
-    }.to_string();
+    let callsign = CALLSIGN.clone();
 
     info!("Callsign for proxy: {}", callsign);
 
@@ -86,281 +184,407 @@ async fn main() -> io::Result<()> {
         ezsockets::tungstenite::run(server, listener).await.unwrap();
     });
 
-    // Initialize the DHT node
-    let dht_identity_name = format!("M17WebProxy{}", std::process::id());
-    let dht_node = DhtNode::new(17171, &dht_identity_name)
-        .expect("Failed to create DHT node");
-
-    // Bootstrap into the ham-dht network
-    dht_node.bootstrap(&CFG.dht_bootstrap, &CFG.dht_port);
-
-    // Give the DHT node time to bootstrap and discover peers.
-    // While waiting, fetch the hostfile as a fallback source.
-    info!("DHT: Waiting for bootstrap to complete...");
-
-    let hostfile_future = fetch_hostfile(&CFG.hostfile_url);
-    let (hostfile_result, _) = tokio::join!(
-        hostfile_future,
-        tokio::time::sleep(Duration::from_secs(5))
-    );
-
-    let hostfile_cache: Option<HostFileCache> = match hostfile_result {
-        Ok(cache) => Some(cache),
-        Err(e) => {
-            warn!("Failed to fetch M17 hostfile: {} - hostfile fallback will not be available", e);
-            None
-        }
-    };
+    tokio::spawn(http::serve(CFG.http_listener_address.clone()));
+
+    // Join the DHT and fetch the hostfile for resolving reflectors
+    init_resolver().await;
 
     for reflector in CFG.subscription.split(",") {
         let reflector_designator = reflector.split("_").next().unwrap().to_string();
 
-        // Resolve reflector address: try DHT first, then hostfile fallback
-        let address = match get_ref_address_from_dht(&dht_node, &reflector_designator).await {
-            Ok(addr) => {
-                info!("DHT: Resolved {} -> {}", reflector_designator, addr);
-                addr
-            }
-            Err(e) => {
-                warn!("DHT: Failed to resolve {}: {} - trying hostfile fallback", reflector_designator, e);
-
-                // Try hostfile fallback
-                match resolve_from_hostfile(&hostfile_cache, &reflector_designator) {
-                    Some(addr) => addr,
-                    None => {
-                        error!("Failed to resolve {} from both DHT and hostfile - skipping", reflector_designator);
-                        continue;
-                    }
-                }
-            }
-        };
-
         for module in reflector.split("_").last().unwrap().chars() {
             info!("Subscribed to {} Module {}", reflector, module);
+
             REFLECTOR_CONNECTIONS.lock().await.push(
-                ReflectorConnection {
-                    reflector: reflector_designator.clone(),
-                    module: module.to_string(),
-                    address: address.clone(),
-                    last_heard: 0,
-                    active_qso: false,
-                    active_qso_meta: QsoMeta {
-                        callsign: "".to_string(),
-                        timestamp: 0
-                    },
-                    messages: Vec::new(),
-                    socket: UdpSocket::bind("0.0.0.0:0").await?
-                }
+                ReflectorConnection::new(&reflector_designator, &module.to_string()).await?
             );
 
         }
     }
 
-    for reflector_connection in REFLECTOR_CONNECTIONS.lock().await.iter() {
-        reflector_connection.socket.connect(&reflector_connection.address).await?;
-    }
+    let (frame_tx, mut frame_rx) = mpsc::unbounded_channel();
 
-    let mut info_to_send = false;
+    let mut housekeeping = tokio::time::interval(Duration::from_secs(1));
+    let mut watchdog = tokio::time::interval(Duration::from_millis(250));
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sigint = signal(SignalKind::interrupt())?;
 
     loop {
-        let mut buf = [0; 1024];
-
-        handle_reconnects(callsign.clone()).await;
-        refresh_module_info().await;
-        if info_to_send {
-            send_module_info().await;
-            info_to_send = false;
+        tokio::select! {
+            Some(frame) = frame_rx.recv() => {
+                handle_frame(frame, &callsign).await;
+            }
+            _ = housekeeping.tick() => {
+                handle_links(&callsign, &frame_tx).await;
+                unlink_idle().await;
+                prune_messages().await;
+            }
+            _ = watchdog.tick() => {
+                expire_qsos().await;
+            }
+            _ = sigterm.recv() => {
+                info!("SIGTERM received, shutting down");
+                break;
+            }
+            _ = sigint.recv() => {
+                info!("SIGINT received, shutting down");
+                break;
+            }
         }
+    }
+
+    shutdown().await;
+    Ok(())
+}
 
-        for reflector_connection in REFLECTOR_CONNECTIONS.lock().await.iter_mut() {
+/// Process a single datagram received from a reflector.
+async fn handle_frame(frame: ReflectorFrame, callsign: &str) {
+    let buf = frame.data.as_slice();
+    if buf.len() < 4 {
+        debug!("Ignoring short packet from {}: {:x?}", frame.reflector, buf);
+        return;
+    }
+    metrics::packet_received(&frame.reflector, &frame.module, &buf[..4]);
 
-            // Try to recv data, this may still fail with `WouldBlock`
-            // if the readiness event is a false positive.
-            match reflector_connection.socket.try_recv(&mut buf) {
+    let mut connections = REFLECTOR_CONNECTIONS.lock().await;
+    let Some(reflector_connection) = connections.iter_mut()
+        .find(|c| c.reflector == frame.reflector && c.module == frame.module) else {
+        return;
+    };
 
-                Ok(_n) => {
+    /*
+        • CONN - Connect to a reflector
+        • ACKN - acknowledge connection
+        • NACK - deny connection
+        • PING - keepalive for the connection from the reflector to the client
+        • PONG - keepalive response from the client to the reflector
+        • DISC - Disconnect (client->reflector or reflector->client)
+    */
+
+    match &buf[..4] {
+        b"DISC" => {
+            warn!("We got disconnected!");
+            reflector_connection.set_link_state(LinkState::Disconnected).await;
+            reflector_connection.link.backoff();
+        }
+        b"ACKN" => {
+            info!("We are linked!");
+            reflector_connection.last_heard = get_epoch().as_secs();
+            reflector_connection.set_link_state(LinkState::Linked).await;
+        }
+        b"NACK" => {
+            warn!("We got denied! Waiting at least a minute before reconnecting...");
+            reflector_connection.set_link_state(LinkState::Denied).await;
+            reflector_connection.link.denied();
+        },
+        b"PING" => {
+            reflector_connection.last_heard = get_epoch().as_secs();
+            reflector_connection.set_link_state(LinkState::Linked).await;
+            if let Err(e) = reflector_connection.socket.send(create_pong_payload(callsign.to_string()).as_slice()).await {
+                error!("Failed to send PONG to {}: {}", reflector_connection.reflector, e);
+            }
+        },
+        // M17 frame!
+        cmd @ (b"M17 " | b"M17P") => {
+
+            let lsf: Lsf;
+            let mut stream_id = None;
+            let mut frame_number = None;
+            let mut packet_protocol = None;
+            let mut is_last = false;
+
+            let mut c2_data = vec![];
+            let mut pm_data = vec![];
+
+            if cmd == b"M17 " {
+
+                let frame = match StreamFrame::parse(buf) {
+                    Ok(frame) => frame,
+                    Err(e) => {
+                        debug!("Dropping invalid stream frame on {} Module {}: {}", reflector_connection.reflector, reflector_connection.module, e);
+                        if reflector_connection.link_stats.record_invalid() {
+                            publish(&reflector_connection.link_state()).await;
+                        }
+                        return;
+                    }
+                };
 
-                    let cmd = str::from_utf8(&buf[..4]).unwrap();
+                if reflector_connection.link_stats.record_frame(frame.stream_id, frame.frame_number, frame.last) {
+                    warn!("Link quality of {} Module {} changed, bad link: {}", reflector_connection.reflector, reflector_connection.module, reflector_connection.link_stats.bad_link);
+                    publish(&reflector_connection.link_state()).await;
+                }
 
-                    /*
-                        • CONN - Connect to a reflector
-                        • ACKN - acknowledge connection
-                        • NACK - deny connection
-                        • PING - keepalive for the connection from the reflector to the client
-                        • PONG - keepalive response from the client to the reflector
-                        • DISC - Disconnect (client->reflector or reflector->client)
-                    */
+                debug!("Stream {:04x} frame {} LSF: {:?}", frame.stream_id, frame.frame_number, frame.lsf);
+                debug!("Voice data: {:x?}", &buf[..52]);
 
-                    match cmd {
-                        "DISC" => {
-                            warn!("We got disconnected!");
-                            reflector_connection.last_heard = 0;
-                        }
-                        "ACKN" => {
-                            info!("We are linked!");
-                        }
-                        "NACK" => {
-                            warn!("We got denied! Waiting a minute before reconnecting...");
-                            reflector_connection.last_heard = get_epoch().as_secs();
-                        }, // Ignored for now -> mrefd sends ping anyway
-                        "PING" => {
-                            let now = get_epoch().as_secs();
-
-                            if reflector_connection.active_qso && now - reflector_connection.active_qso_meta.timestamp > 1 {
-                                reflector_connection.active_qso = false;
-                                info_to_send = true;
-                            }
-                            reflector_connection.last_heard = now;
-                            reflector_connection.socket.send(create_pong_payload(callsign.clone()).as_slice()).await?;
-                        },
-                        // M17 frame!
-                        "M17 " | "M17P" => {
-
-                            let mut src_call: String = "".to_string();
-                            let mut dst_call: String = "".to_string();
-
-                            let mut c2_data = vec![];
-                            let mut pm_data = vec![];
-
-                            if cmd == "M17 " {
-
-                                dst_call = decode_callsign(&buf[6..12]);
-                                src_call = decode_callsign(&buf[12..18]);
-
-                                // Codec 2 stream
-                                c2_data = buf[36..52].to_vec();
-
-                                debug!("Packet src_call: {:?}", src_call);
-                                debug!("Packet dst_call: {:?}", dst_call);
-                                debug!("Voice data: {:x?}", &buf[..52])
-
-                            } else {
-
-                                dst_call = decode_callsign(&buf[4..10]);
-                                src_call = decode_callsign(&buf[10..16]);
-
-                                // Find the last non-zero byte
-                                let last_non_zero = buf[35..].iter()
-                                    .rposition(|&x| x != 0)
-                                    .unwrap_or(0);
-
-                                // Create a vector with all bytes up to and including the last non-zero byte
-                                pm_data = buf[35..(35 + last_non_zero - 2)].to_vec();
-
-                                debug!("Packet src_call: {:?}", src_call);
-                                debug!("Packet dst_call: {:?}", dst_call);
-                                debug!("Packet data: {:x?}", &buf[..52]);
-
-                                reflector_connection.messages.push( MsgData {
-                                    callsign: src_call.clone(),
-                                    message: str::from_utf8(pm_data.as_slice()).unwrap().to_string(),
-                                    timestamp: get_epoch().as_secs(),
-                                });
-                                info_to_send = true;
-
-                            }
-
-                            // Last frame 1st byte of last stream is always > 0x80
-                            let mut is_last = false;
-                            if buf[34] >= 0x80 {
-                                debug!("Received last frame!");
-                                is_last = true;
-                            }
-
-                            // Serialize as json and send to all connected websocket clients
-                            WS_SESSIONS.lock().await.iter().for_each(|session|{
-                                if session.subscription.reflector == reflector_connection.reflector && session.subscription.module == reflector_connection.module && session.info_connection == false {
-                                    let send_payload = WsPayload {
-                                        reflector: reflector_connection.reflector.to_string(),
-                                        module: reflector_connection.module.to_string(),
-                                        src_call: src_call.clone(),
-                                        dest_call: dst_call.clone(),
-                                        c2_stream: c2_data.clone(),
-                                        pm_stream: pm_data.clone(),
-                                        done: is_last
-                                    };
-                                    session.ws_session.handle.text(serde_json::to_string(&send_payload).unwrap()).unwrap();
-                                }
-                            });
-
-                            if reflector_connection.active_qso == false {
-                                reflector_connection.active_qso = true;
-                                info_to_send = true;
-                            }
-
-                            reflector_connection.active_qso_meta.callsign = src_call.clone();
-                            reflector_connection.active_qso_meta.timestamp = get_epoch().as_secs();
-                        }
-                        _ => {
-                            debug!(" {:x?}", &buf);
-                        }
+                // Last frame has the end-of-stream bit set in the frame number
+                if frame.last {
+                    debug!("Received last frame!");
+                    is_last = true;
+                }
+
+                let (started, ended) = reflector_connection.qsos.frame(&frame);
+                if let Some(qso) = started {
+                    info!("QSO started by {} on {} Module {} (stream {:04x})", qso.callsign, reflector_connection.reflector, reflector_connection.module, qso.stream_id);
+                    record_start(&reflector_connection.reflector, &reflector_connection.module, &qso);
+                    publish(&InfoEvent::QsoStart {
+                        reflector: reflector_connection.reflector.clone(),
+                        module: reflector_connection.module.clone(),
+                        qso,
+                    }).await;
+                }
+
+                record_audio(&reflector_connection.reflector, &reflector_connection.module, frame.stream_id, &frame.payload);
+                reflector_connection.replay.push(&frame);
+
+                if frame.lsf.has_text() {
+                    if let Some(text) = reflector_connection.text_assembler.push(frame.stream_id, &frame.lsf.meta) {
+                        info!("Status text from {} on {} Module {}: {}", frame.lsf.src, reflector_connection.reflector, reflector_connection.module, text);
+                        reflector_connection.qsos.set_text(frame.stream_id, text.clone());
+                        publish(&InfoEvent::Text {
+                            reflector: reflector_connection.reflector.clone(),
+                            module: reflector_connection.module.clone(),
+                            callsign: frame.lsf.src.clone(),
+                            stream_id: frame.stream_id,
+                            text,
+                            timestamp: get_epoch().as_secs(),
+                        }).await;
                     }
-                    break;
                 }
-                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
-                    continue;
+
+                if let Some(position) = frame.lsf.gnss() {
+                    reflector_connection.qsos.set_position(frame.stream_id, position);
+                }
+
+                if let Some(qso) = ended {
+                    info!("QSO of {} on {} Module {} ended after {} ms ({} frames)", qso.callsign, reflector_connection.reflector, reflector_connection.module, qso.duration_ms, qso.frames);
+                    reflector_connection.text_assembler.finish(qso.stream_id);
+                    record_qso(qso.to_record(&reflector_connection.reflector, &reflector_connection.module));
+                    record_end(&reflector_connection.reflector, &reflector_connection.module, &qso, QsoEndReason::Eot);
+                    publish(&InfoEvent::QsoEnd {
+                        reflector: reflector_connection.reflector.clone(),
+                        module: reflector_connection.module.clone(),
+                        reason: QsoEndReason::Eot,
+                        qso,
+                    }).await;
+                }
+
+                // Codec 2 stream
+                c2_data = frame.payload;
+                stream_id = Some(frame.stream_id);
+                frame_number = Some(frame.frame_number);
+                lsf = frame.lsf;
+
+            } else {
+
+                let packet = match PacketFrame::parse(buf) {
+                    Ok(packet) => packet,
+                    Err(e) => {
+                        warn!("Malformed packet on {} Module {}: {}", reflector_connection.reflector, reflector_connection.module, e);
+                        debug!("Packet data: {:x?}", buf);
+                        publish(&InfoEvent::MalformedPacket {
+                            reflector: reflector_connection.reflector.clone(),
+                            module: reflector_connection.module.clone(),
+                            reason: e.to_string(),
+                            timestamp: get_epoch().as_secs(),
+                        }).await;
+                        return;
+                    }
+                };
+
+                debug!("Packet LSF: {:?}", packet.lsf);
+                debug!("Packet {:?} data: {:x?}", packet.protocol, packet.data);
+
+                let text = packet.sms_text();
+                if let Some(message) = &text {
+                    let message = reflector_connection.messages.push(MsgData::new(packet.lsf.src.clone(), message.clone()));
+                    publish(&InfoEvent::Message {
+                        reflector: reflector_connection.reflector.clone(),
+                        module: reflector_connection.module.clone(),
+                        message,
+                    }).await;
                 }
-                Err(e) => {
-                    return Err(e);
+
+                record_message(MessageRecord {
+                    reflector: reflector_connection.reflector.clone(),
+                    module: reflector_connection.module.clone(),
+                    src_call: packet.lsf.src.clone(),
+                    dst_call: packet.lsf.dst.clone(),
+                    protocol: packet.protocol.name().to_string(),
+                    text: text.clone(),
+                    data: packet.data.clone(),
+                    timestamp: get_epoch().as_secs(),
+                });
+
+                publish(&InfoEvent::Packet {
+                    reflector: reflector_connection.reflector.clone(),
+                    module: reflector_connection.module.clone(),
+                    src_call: packet.lsf.src.clone(),
+                    dest_call: packet.lsf.dst.clone(),
+                    protocol: packet.protocol,
+                    protocol_id: packet.protocol_id,
+                    text: text.clone(),
+                    data: packet.data.clone(),
+                    timestamp: get_epoch().as_secs(),
+                }).await;
+
+                // Stream clients get the SMS text without terminator, other protocols as is
+                pm_data = match text {
+                    Some(message) => message.into_bytes(),
+                    None => packet.data,
+                };
+                packet_protocol = Some(packet.protocol);
+                lsf = packet.lsf;
+
+            }
+
+            let gnss = lsf.gnss();
+            if let Some(position) = &gnss {
+                let changed = reflector_connection.positions.get(&lsf.src)
+                    .is_none_or(|report| report.position != *position);
+                let report = PositionReport {
+                    position: position.clone(),
+                    timestamp: get_epoch().as_secs(),
+                };
+                reflector_connection.positions.insert(lsf.src.clone(), report.clone());
+
+                if changed {
+                    debug!("New position for {}: {:?}", lsf.src, position);
+                    publish(&InfoEvent::Position {
+                        reflector: reflector_connection.reflector.clone(),
+                        module: reflector_connection.module.clone(),
+                        callsign: lsf.src.clone(),
+                        report,
+                    }).await;
                 }
             }
+
+            let ws_sessions = WS_SESSIONS.lock().await;
+            let subscribers: Vec<_> = ws_sessions.values()
+                .filter(|session| session.subscribed(&reflector_connection.reflector, &reflector_connection.module))
+                .collect();
+
+            #[cfg(feature = "transcode")]
+            let listened = listen::is_listened(&reflector_connection.reflector, &reflector_connection.module).await;
+            #[cfg(not(feature = "transcode"))]
+            let listened = false;
+
+            // Decode once per frame for all clients that asked for decoded audio
+            let wants = |format| subscribers.iter().any(|session| session.format == format);
+            let pcm = if !c2_data.is_empty() && (listened || wants(AudioFormat::Pcm16) || wants(AudioFormat::Opus)) {
+                reflector_connection.transcoder.decode(&c2_data)
+            } else {
+                None
+            };
+            let opus = match &pcm {
+                Some(pcm) if wants(AudioFormat::Opus) => reflector_connection.transcoder.encode_opus(pcm),
+                _ => vec![],
+            };
+
+            #[cfg(feature = "transcode")]
+            if let (true, Some(pcm)) = (listened, &pcm) {
+                listen::push_audio(&reflector_connection.reflector, &reflector_connection.module, pcm).await;
+            }
+
+            // Serialize as json and send to all connected websocket clients
+            for session in subscribers {
+                let send_payload = WsPayload {
+                    reflector: reflector_connection.reflector.to_string(),
+                    module: reflector_connection.module.to_string(),
+                    src_call: lsf.src.clone(),
+                    dest_call: lsf.dst.clone(),
+                    stream_id,
+                    frame_number,
+                    packet_protocol,
+                    lsf: lsf.clone(),
+                    gnss: gnss.clone(),
+                    format: session.format,
+                    c2_stream: if session.format == AudioFormat::Codec2 { c2_data.clone() } else { vec![] },
+                    pm_stream: pm_data.clone(),
+                    done: is_last,
+                    replay: false,
+                };
+                send_stream_frame(&session.ws_session.handle, &send_payload, pcm.as_deref(), &opus);
+            }
+        }
+        _ => {
+            debug!(" {:x?}", &buf);
         }
-        tokio::time::sleep(Duration::from_millis(1)).await;
     }
 }
 
-async fn send_module_info() {
-    for session in WS_SESSIONS.lock().await.iter() {
-        if session.info_connection {
-            session.ws_session.handle.text(serde_json::to_string(&get_module_infos().await).unwrap()).unwrap();
-        }
-    }
+async fn add_message(reflector: &str, module: &str, message: MsgData) {
+    let Some(message) = REFLECTOR_CONNECTIONS.lock().await.iter_mut()
+        .find(|c| c.reflector == reflector && c.module == module)
+        .map(|connection| connection.messages.push(message)) else {
+        return;
+    };
+    publish(&InfoEvent::Message {
+        reflector: reflector.to_string(),
+        module: module.to_string(),
+        message,
+    }).await;
 }
 
-async fn get_module_infos() -> Vec<ModuleInfo> {
-    let mut mod_info = vec![];
-
-    for info in REFLECTOR_CONNECTIONS.lock().await.iter() {
-        mod_info.push(
-            ModuleInfo {
-                reflector: info.reflector.clone(),
-                module: info.module.clone(),
-                last_heard: info.last_heard.clone(),
-                last_qso_call: info.active_qso_meta.callsign.clone(),
-                last_qso_time: info.active_qso_meta.timestamp.clone(),
-                active_qso: info.active_qso.clone(),
-                messages: info.messages.clone(),
-            }
-        );
+/// Page through the retained messages of a module, newest first.
+async fn message_page(reflector: &str, module: &str, before: Option<u64>, limit: Option<usize>) -> Option<MessagePage> {
+    REFLECTOR_CONNECTIONS.lock().await.iter()
+        .find(|c| c.reflector == reflector && c.module == module)
+        .map(|c| c.messages.page(before, limit))
+}
+
+fn module_info(info: &ReflectorConnection) -> ModuleInfo {
+    let latest = info.qsos.latest();
+    ModuleInfo {
+        reflector: info.reflector.clone(),
+        module: info.module.clone(),
+        last_heard: info.last_heard,
+        linked: info.link.state == LinkState::Linked,
+        link_state: info.link.state,
+        last_qso_call: latest.map(|qso| qso.callsign.clone()).unwrap_or_default(),
+        last_qso_time: latest.map(|qso| qso.last_frame).unwrap_or_default(),
+        last_qso_text: latest.and_then(|qso| qso.text.clone()),
+        active_qso: info.qsos.is_active(),
+        active_qsos: info.qsos.active(),
+        message_count: info.messages.len(),
+        last_message: info.messages.latest().cloned(),
+        positions: info.positions.clone(),
+        link_stats: info.link_stats.clone(),
     }
-    mod_info
 }
 
-async fn refresh_module_info() {
-    ACTIVE_MOULES.lock().await.modules = vec![];
-    for info in REFLECTOR_CONNECTIONS.lock().await.iter() {
-        ACTIVE_MOULES.lock().await.modules.push(
-            ModuleInfo {
-                reflector: info.reflector.clone(),
-                module: info.module.clone(),
-                last_heard: info.last_heard.clone(),
-                last_qso_call: "".to_string(),
-                last_qso_time: info.active_qso_meta.timestamp.clone(),
-                active_qso: false,
-                messages: info.messages.clone(),
-            }
-        );
+/// End QSOs whose end-of-stream frame got lost.
+async fn expire_qsos() {
+    let mut events = vec![];
+    for reflector_connection in REFLECTOR_CONNECTIONS.lock().await.iter_mut() {
+        for qso in reflector_connection.qsos.expire() {
+            info!("QSO of {} on {} Module {} timed out after {} ms ({} frames)", qso.callsign, reflector_connection.reflector, reflector_connection.module, qso.duration_ms, qso.frames);
+            record_qso(qso.to_record(&reflector_connection.reflector, &reflector_connection.module));
+            record_end(&reflector_connection.reflector, &reflector_connection.module, &qso, QsoEndReason::Timeout);
+            reflector_connection.replay.finish(qso.stream_id);
+            reflector_connection.text_assembler.finish(qso.stream_id);
+            events.push(InfoEvent::QsoEnd {
+                reflector: reflector_connection.reflector.clone(),
+                module: reflector_connection.module.clone(),
+                reason: QsoEndReason::Timeout,
+                qso,
+            });
+        }
+    }
+
+    for event in events.iter() {
+        publish(event).await;
     }
 }
 
-async fn handle_reconnects(callsign: String) {
+/// Drop messages older than `M17WEB_PROXY_MESSAGE_MAX_AGE` and positions
+/// older than `M17WEB_PROXY_POSITION_MAX_AGE`.
+async fn prune_messages() {
+    let oldest_position = get_epoch().as_secs().saturating_sub(CFG.position_max_age);
     for reflector_connection in REFLECTOR_CONNECTIONS.lock().await.iter_mut() {
-        let now = get_epoch().as_secs();
-        if now - reflector_connection.last_heard > 60 {
-            let module = reflector_connection.module.clone();
-            let conn_payload = create_conn_payload("LSTN".to_string(), callsign.clone(), module);
-            let _len = reflector_connection.socket.send(&conn_payload).await;
-            reflector_connection.last_heard = get_epoch().as_secs();
+        reflector_connection.messages.prune();
+        if CFG.position_max_age > 0 {
+            reflector_connection.positions.retain(|_, report| report.timestamp >= oldest_position);
         }
     }
 }
@@ -0,0 +1,121 @@
+use std::collections::VecDeque;
+
+use serde::Serialize;
+
+use crate::{get_epoch, CFG};
+
+/// Default number of messages returned by one page.
+const DEFAULT_PAGE_SIZE: usize = 50;
+/// Upper bound for the messages returned by one page.
+const MAX_PAGE_SIZE: usize = 500;
+
+#[derive(Serialize, Clone, Debug)]
+pub struct MsgData {
+    /// Increasing per module, the paging cursor.
+    pub id: u64,
+    pub callsign: String,
+    pub message: String,
+    pub timestamp: u64
+}
+
+impl MsgData {
+    /// A message received or sent now. Its ID is assigned when it is added to a history.
+    pub fn new(callsign: String, message: String) -> MsgData {
+        MsgData {
+            id: 0,
+            callsign,
+            message,
+            timestamp: get_epoch().as_secs(),
+        }
+    }
+}
+
+/// A page of a module's message history, newest first.
+#[derive(Serialize)]
+pub struct MessagePage {
+    pub messages: Vec<MsgData>,
+    pub total: usize,
+    pub has_more: bool,
+}
+
+/// Recent packet messages of a module, bounded by `M17WEB_PROXY_MESSAGE_LIMIT`
+/// entries and `M17WEB_PROXY_MESSAGE_MAX_AGE` seconds.
+#[derive(Default)]
+pub struct MessageHistory {
+    messages: VecDeque<MsgData>,
+    next_id: u64,
+}
+
+impl MessageHistory {
+    /// Add a message, returning it with its assigned ID.
+    pub fn push(&mut self, mut message: MsgData) -> MsgData {
+        self.next_id += 1;
+        message.id = self.next_id;
+        self.messages.push_back(message.clone());
+        self.prune();
+        message
+    }
+
+    /// Drop messages exceeding the configured count or age.
+    pub fn prune(&mut self) {
+        while self.messages.len() > CFG.message_limit {
+            self.messages.pop_front();
+        }
+        if CFG.message_max_age > 0 {
+            let oldest = get_epoch().as_secs().saturating_sub(CFG.message_max_age);
+            while self.messages.front().is_some_and(|m| m.timestamp < oldest) {
+                self.messages.pop_front();
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn latest(&self) -> Option<&MsgData> {
+        self.messages.back()
+    }
+
+    /// Messages with an ID below `before` (all if unset), newest first.
+    pub fn page(&self, before: Option<u64>, limit: Option<usize>) -> MessagePage {
+        let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE);
+        let mut older = self.messages.iter()
+            .rev()
+            .filter(|m| before.is_none_or(|before| m.id < before));
+
+        let messages: Vec<MsgData> = older.by_ref().take(limit).cloned().collect();
+        MessagePage {
+            messages,
+            total: self.messages.len(),
+            has_more: older.next().is_some(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn page_with_equal_timestamps() {
+        let mut history = MessageHistory::default();
+        for i in 0..5 {
+            history.push(MsgData::new("N0CALL".to_string(), format!("Message {}", i)));
+        }
+        assert!(history.messages.iter().all(|m| m.timestamp == history.messages[0].timestamp));
+
+        let first = history.page(None, Some(2));
+        assert_eq!(first.messages.iter().map(|m| m.id).collect::<Vec<_>>(), vec![5, 4]);
+        assert!(first.has_more);
+
+        let second = history.page(first.messages.last().map(|m| m.id), Some(2));
+        assert_eq!(second.messages.iter().map(|m| m.id).collect::<Vec<_>>(), vec![3, 2]);
+        assert!(second.has_more);
+
+        let third = history.page(second.messages.last().map(|m| m.id), Some(2));
+        assert_eq!(third.messages.iter().map(|m| m.id).collect::<Vec<_>>(), vec![1]);
+        assert!(!third.has_more);
+        assert_eq!(third.total, 5);
+    }
+}
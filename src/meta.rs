@@ -0,0 +1,262 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+/// Length of the META field in the LSF.
+pub const META_LEN: usize = 14;
+
+const FEET_TO_METERS: f32 = 0.3048;
+const MPH_TO_KMH: f32 = 1.609344;
+
+/// Origin of a GNSS META position report.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum GnssSource {
+    M17Client,
+    OpenRtx,
+    Other,
+}
+
+/// Kind of station reporting a GNSS position.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StationType {
+    Fixed,
+    Mobile,
+    Handheld,
+    Other,
+}
+
+/// Station position decoded from a GNSS META field.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct GnssPosition {
+    pub source: GnssSource,
+    pub station_type: StationType,
+    /// Degrees, positive north.
+    pub latitude: f64,
+    /// Degrees, positive east.
+    pub longitude: f64,
+    /// Meters above sea level.
+    pub altitude: Option<f32>,
+    /// Kilometers per hour.
+    pub speed: Option<f32>,
+    /// Degrees from true north.
+    pub bearing: Option<u16>,
+}
+
+impl GnssPosition {
+    /// Decode a 14 byte GNSS META block as defined in the M17 specification v1.0,
+    /// the revision whose TYPE field signals it with encryption subtype 1.
+    /// Returns `None` if the block is too short or the coordinates are out of range.
+    ///
+    /// Layout: source (1 byte), station type (1), latitude degrees (1) and
+    /// 1/65536 fraction (2), longitude degrees (1) and fraction (2), flags (1),
+    /// altitude in feet + 1500 (2), bearing in degrees (2), speed in mph (1).
+    /// Flags: bit 0 south, bit 1 west, bit 2 altitude valid, bit 3 speed and bearing valid.
+    pub fn decode(meta: &[u8]) -> Option<GnssPosition> {
+        if meta.len() < META_LEN {
+            return None;
+        }
+
+        let flags = meta[8];
+        let south = flags & 0x01 != 0;
+        let west = flags & 0x02 != 0;
+        let altitude_valid = flags & 0x04 != 0;
+        let velocity_valid = flags & 0x08 != 0;
+
+        let latitude = meta[2] as f64 + u16::from_be_bytes([meta[3], meta[4]]) as f64 / 65536.0;
+        let longitude = meta[5] as f64 + u16::from_be_bytes([meta[6], meta[7]]) as f64 / 65536.0;
+        if latitude > 90.0 || longitude > 180.0 {
+            return None;
+        }
+
+        let altitude = (u16::from_be_bytes([meta[9], meta[10]]) as f32 - 1500.0) * FEET_TO_METERS;
+        let bearing = u16::from_be_bytes([meta[11], meta[12]]);
+        let speed = meta[13] as f32 * MPH_TO_KMH;
+
+        Some(GnssPosition {
+            source: match meta[0] {
+                0 => GnssSource::M17Client,
+                1 => GnssSource::OpenRtx,
+                _ => GnssSource::Other,
+            },
+            station_type: match meta[1] {
+                0 => StationType::Fixed,
+                1 => StationType::Mobile,
+                2 => StationType::Handheld,
+                _ => StationType::Other,
+            },
+            latitude: if south { -latitude } else { latitude },
+            longitude: if west { -longitude } else { longitude },
+            altitude: altitude_valid.then_some(altitude),
+            speed: velocity_valid.then_some(speed),
+            bearing: velocity_valid.then_some(bearing),
+        })
+    }
+}
+
+/// Length of one text block carried in a text-data META field.
+pub const TEXT_BLOCK_LEN: usize = 13;
+
+/// Reassembles the up to four text blocks of text-data META streams, per stream ID.
+///
+/// The first META byte is a control byte: the upper nibble is a mask of all
+/// blocks making up the message, the lower nibble marks the block carried in
+/// this frame. The remaining 13 bytes are the block's text.
+#[derive(Default)]
+pub struct TextAssembler {
+    partial: HashMap<u16, PartialText>,
+}
+
+/// Blocks of one stream's text received so far.
+#[derive(Default)]
+struct PartialText {
+    total_mask: u8,
+    received_mask: u8,
+    blocks: [[u8; TEXT_BLOCK_LEN]; 4],
+    complete: bool,
+}
+
+impl TextAssembler {
+    /// Feed the META field of a frame belonging to `stream_id`.
+    /// Returns the assembled text the first time all blocks of a message were received.
+    pub fn push(&mut self, stream_id: u16, meta: &[u8]) -> Option<String> {
+        if meta.len() < META_LEN {
+            return None;
+        }
+
+        let total_mask = meta[0] >> 4;
+        let block_mask = meta[0] & 0x0F;
+        if total_mask == 0 || block_mask.count_ones() != 1 || block_mask & total_mask == 0 {
+            return None;
+        }
+
+        let partial = self.partial.entry(stream_id).or_default();
+        if partial.total_mask != total_mask {
+            *partial = PartialText {
+                total_mask,
+                ..Default::default()
+            };
+        }
+
+        let index = block_mask.trailing_zeros() as usize;
+        partial.blocks[index].copy_from_slice(&meta[1..META_LEN]);
+        partial.received_mask |= block_mask;
+
+        if partial.complete || partial.received_mask != partial.total_mask {
+            return None;
+        }
+        partial.complete = true;
+
+        let text: Vec<u8> = (0..4)
+            .filter(|i| partial.total_mask & (1 << i) != 0)
+            .flat_map(|i| partial.blocks[i])
+            .collect();
+        let text = String::from_utf8_lossy(&text)
+            .trim_end_matches(['\0', ' '])
+            .to_string();
+
+        (!text.is_empty()).then_some(text)
+    }
+
+    /// Forget the text of a stream that ended.
+    pub fn finish(&mut self, stream_id: u16) {
+        self.partial.remove(&stream_id);
+    }
+
+    /// Forget all streams, when the module is unlinked.
+    pub fn clear(&mut self) {
+        self.partial.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // OpenRTX handheld at 45.5°N 90.25°W, 1000 ft, 50 mph towards 270°
+    const GNSS_FIXTURE: [u8; META_LEN] = [
+        0x01, 0x02,
+        45, 0x80, 0x00,
+        90, 0x40, 0x00,
+        0x0E,
+        0x09, 0xC4,
+        0x01, 0x0E,
+        50,
+    ];
+
+    #[test]
+    fn gnss_decode() {
+        let position = GnssPosition::decode(&GNSS_FIXTURE).unwrap();
+        assert_eq!(position.source, GnssSource::OpenRtx);
+        assert_eq!(position.station_type, StationType::Handheld);
+        assert_eq!(position.latitude, 45.5);
+        assert_eq!(position.longitude, -90.25);
+        assert!((position.altitude.unwrap() - 304.8).abs() < 0.01);
+        assert!((position.speed.unwrap() - 80.467).abs() < 0.01);
+        assert_eq!(position.bearing, Some(270));
+    }
+
+    #[test]
+    fn gnss_decode_invalid() {
+        assert_eq!(GnssPosition::decode(&GNSS_FIXTURE[..10]), None);
+
+        // Position only, north and east
+        let mut meta = GNSS_FIXTURE;
+        meta[8] = 0x00;
+        let position = GnssPosition::decode(&meta).unwrap();
+        assert_eq!((position.latitude, position.longitude), (45.5, 90.25));
+        assert_eq!((position.altitude, position.speed, position.bearing), (None, None, None));
+
+        meta[2] = 91;
+        assert_eq!(GnssPosition::decode(&meta), None);
+    }
+
+    fn text_block(control: u8, text: &[u8; TEXT_BLOCK_LEN]) -> [u8; META_LEN] {
+        let mut meta = [0; META_LEN];
+        meta[0] = control;
+        meta[1..].copy_from_slice(text);
+        meta
+    }
+
+    #[test]
+    fn text_two_blocks() {
+        let first = text_block(0x31, b"Hello from M1");
+        let second = text_block(0x32, b"7 proxy      ");
+
+        let mut assembler = TextAssembler::default();
+        assert_eq!(assembler.push(1, &second), None);
+        assert_eq!(assembler.push(1, &first).as_deref(), Some("Hello from M17 proxy"));
+        // Repeated blocks of the same message are not reported again
+        assert_eq!(assembler.push(1, &first), None);
+        assert_eq!(assembler.push(1, &second), None);
+
+        // A new stream starts over
+        assert_eq!(assembler.push(2, &first), None);
+        assert_eq!(assembler.push(2, &second).as_deref(), Some("Hello from M17 proxy"));
+
+        // Until the stream ends
+        assembler.finish(1);
+        assert_eq!(assembler.push(1, &first), None);
+        assert_eq!(assembler.push(1, &second).as_deref(), Some("Hello from M17 proxy"));
+    }
+
+    #[test]
+    fn text_interleaved_streams() {
+        let mut assembler = TextAssembler::default();
+        assert_eq!(assembler.push(1, &text_block(0x31, b"Hello from M1")), None);
+        assert_eq!(assembler.push(2, &text_block(0x31, b"Greetings fro")), None);
+        assert_eq!(assembler.push(1, &text_block(0x32, b"7 proxy      ")).as_deref(), Some("Hello from M17 proxy"));
+        assert_eq!(assembler.push(2, &text_block(0x32, b"m N0CALL     ")).as_deref(), Some("Greetings from N0CALL"));
+    }
+
+    #[test]
+    fn text_invalid_control() {
+        let mut assembler = TextAssembler::default();
+        assert_eq!(assembler.push(1, &text_block(0x00, b"Hello from M1")), None);
+        assert_eq!(assembler.push(1, &text_block(0x13, b"Hello from M1")), None);
+        assert_eq!(assembler.push(1, &text_block(0x12, b"Hello from M1")), None);
+        assert_eq!(assembler.push(1, &[0x11]), None);
+        assert_eq!(assembler.push(1, &text_block(0x11, b"Hello        ")).as_deref(), Some("Hello"));
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Write};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use serde::Serialize;
+
+use crate::link::LinkState;
+use crate::websocket::{connections_accepted, WS_SESSIONS};
+use crate::REFLECTOR_CONNECTIONS;
+
+lazy_static! {
+    static ref METRICS: Mutex<Metrics> = Mutex::new(Metrics::default());
+}
+
+/// Counters that are not already kept on the connections or sessions.
+#[derive(Default)]
+struct Metrics {
+    /// Datagrams by reflector, module and command.
+    packets: BTreeMap<(String, String, String), u64>,
+    /// CONN/LSTN requests sent by reflector and module.
+    reconnects: BTreeMap<(String, String), u64>,
+    /// Bytes sent to clients by channel.
+    bytes_sent: BTreeMap<&'static str, u64>,
+    /// DHT lookups by outcome.
+    dht_lookups: BTreeMap<&'static str, u64>,
+    dht_lookup_seconds: f64,
+    /// Hostfile fetches by outcome.
+    hostfile_fetches: BTreeMap<&'static str, u64>,
+    hostfile_available: bool,
+}
+
+/// Account a datagram received from a reflector.
+pub fn packet_received(reflector: &str, module: &str, command: &[u8]) {
+    let command = match command {
+        b"PING" | b"ACKN" | b"NACK" | b"DISC" | b"M17 " | b"M17P" => String::from_utf8_lossy(command).to_string(),
+        _ => "other".to_string(),
+    };
+    let mut metrics = METRICS.lock().unwrap();
+    *metrics.packets.entry((reflector.to_string(), module.to_string(), command)).or_default() += 1;
+}
+
+/// Account a link request sent to a reflector.
+pub fn reconnect(reflector: &str, module: &str) {
+    let mut metrics = METRICS.lock().unwrap();
+    *metrics.reconnects.entry((reflector.to_string(), module.to_string())).or_default() += 1;
+}
+
+/// Account bytes sent to clients on a channel (`info`, `stream`, `reply` or `listen`).
+pub fn bytes_sent(channel: &'static str, bytes: usize) {
+    *METRICS.lock().unwrap().bytes_sent.entry(channel).or_default() += bytes as u64;
+}
+
+pub fn dht_lookup(duration: Duration, success: bool) {
+    let mut metrics = METRICS.lock().unwrap();
+    *metrics.dht_lookups.entry(if success { "success" } else { "failure" }).or_default() += 1;
+    metrics.dht_lookup_seconds += duration.as_secs_f64();
+}
+
+pub fn hostfile_fetch(success: bool) {
+    let mut metrics = METRICS.lock().unwrap();
+    *metrics.hostfile_fetches.entry(if success { "success" } else { "failure" }).or_default() += 1;
+    metrics.hostfile_available |= success;
+}
+
+/// Outcome of reflector discovery, as reported by the readiness endpoint.
+#[derive(Serialize)]
+pub struct DiscoveryStatus {
+    pub dht_lookups_ok: u64,
+    pub dht_lookups_failed: u64,
+    pub hostfile_available: bool,
+}
+
+pub fn discovery() -> DiscoveryStatus {
+    let metrics = METRICS.lock().unwrap();
+    DiscoveryStatus {
+        dht_lookups_ok: metrics.dht_lookups.get("success").copied().unwrap_or_default(),
+        dht_lookups_failed: metrics.dht_lookups.get("failure").copied().unwrap_or_default(),
+        hostfile_available: metrics.hostfile_available,
+    }
+}
+
+/// Prometheus text exposition builder.
+#[derive(Default)]
+struct Exposition(String);
+
+impl Exposition {
+    fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.0, "# HELP {} {}", name, help);
+        let _ = writeln!(self.0, "# TYPE {} {}", name, kind);
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl Display) {
+        let labels: Vec<String> = labels.iter()
+            .map(|(key, value)| format!("{}=\"{}\"", key, value.replace('\\', "\\\\").replace('"', "\\\"")))
+            .collect();
+        if labels.is_empty() {
+            let _ = writeln!(self.0, "{} {}", name, value);
+        } else {
+            let _ = writeln!(self.0, "{}{{{}}} {}", name, labels.join(","), value);
+        }
+    }
+}
+
+/// Render all metrics in the Prometheus text format.
+pub async fn render() -> String {
+    let mut out = Exposition::default();
+
+    {
+        let connections = REFLECTOR_CONNECTIONS.lock().await;
+
+        out.family("m17web_frames_received_total", "counter", "Valid M17 stream frames received.");
+        for c in connections.iter() {
+            out.sample("m17web_frames_received_total", &[("reflector", &c.reflector), ("module", &c.module)], c.link_stats.frames_received);
+        }
+        out.family("m17web_frames_invalid_total", "counter", "Stream frames dropped for length or CRC errors.");
+        for c in connections.iter() {
+            out.sample("m17web_frames_invalid_total", &[("reflector", &c.reflector), ("module", &c.module)], c.link_stats.invalid_frames);
+        }
+        out.family("m17web_frames_lost_total", "counter", "Stream frames missing from the frame numbering.");
+        for c in connections.iter() {
+            out.sample("m17web_frames_lost_total", &[("reflector", &c.reflector), ("module", &c.module)], c.link_stats.frames_lost);
+        }
+        out.family("m17web_frame_jitter_ms", "gauge", "Smoothed frame arrival jitter in milliseconds.");
+        for c in connections.iter() {
+            out.sample("m17web_frame_jitter_ms", &[("reflector", &c.reflector), ("module", &c.module)], c.link_stats.jitter_ms);
+        }
+        out.family("m17web_link_up", "gauge", "Whether the module is linked to its reflector.");
+        for c in connections.iter() {
+            out.sample("m17web_link_up", &[("reflector", &c.reflector), ("module", &c.module)], (c.link.state == LinkState::Linked) as u8);
+        }
+        out.family("m17web_link_state", "gauge", "Current link state of the module, 1 for the active state.");
+        for c in connections.iter() {
+            for state in LinkState::ALL {
+                out.sample("m17web_link_state", &[("reflector", &c.reflector), ("module", &c.module), ("state", state.as_str())], (c.link.state == state) as u8);
+            }
+        }
+    }
+
+    {
+        let sessions = WS_SESSIONS.lock().await;
+        let info = sessions.values().filter(|s| s.info_connection).count();
+        out.family("m17web_ws_clients", "gauge", "Connected WebSocket clients by type.");
+        out.sample("m17web_ws_clients", &[("type", "info")], info);
+        out.sample("m17web_ws_clients", &[("type", "stream")], sessions.len() - info);
+        out.family("m17web_ws_connections_total", "counter", "WebSocket connections accepted.");
+        out.sample("m17web_ws_connections_total", &[], connections_accepted());
+    }
+
+    let metrics = METRICS.lock().unwrap();
+
+    out.family("m17web_packets_received_total", "counter", "Datagrams received from reflectors by command.");
+    for ((reflector, module, command), count) in metrics.packets.iter() {
+        out.sample("m17web_packets_received_total", &[("reflector", reflector), ("module", module), ("command", command)], count);
+    }
+    out.family("m17web_reconnects_total", "counter", "Link requests (CONN/LSTN) sent to reflectors.");
+    for ((reflector, module), count) in metrics.reconnects.iter() {
+        out.sample("m17web_reconnects_total", &[("reflector", reflector), ("module", module)], count);
+    }
+    out.family("m17web_bytes_sent_total", "counter", "Bytes sent to clients by channel.");
+    for (channel, bytes) in metrics.bytes_sent.iter() {
+        out.sample("m17web_bytes_sent_total", &[("channel", channel)], bytes);
+    }
+    out.family("m17web_dht_lookups_total", "counter", "Reflector lookups on the DHT by outcome.");
+    for (outcome, count) in metrics.dht_lookups.iter() {
+        out.sample("m17web_dht_lookups_total", &[("outcome", outcome)], count);
+    }
+    out.family("m17web_dht_lookup_seconds_total", "counter", "Total time spent on DHT lookups.");
+    out.sample("m17web_dht_lookup_seconds_total", &[], metrics.dht_lookup_seconds);
+    out.family("m17web_hostfile_fetches_total", "counter", "Hostfile fetches by outcome.");
+    for (outcome, count) in metrics.hostfile_fetches.iter() {
+        out.sample("m17web_hostfile_fetches_total", &[("outcome", outcome)], count);
+    }
+    out.family("m17web_hostfile_available", "gauge", "Whether a hostfile was fetched for fallback resolution.");
+    out.sample("m17web_hostfile_available", &[], metrics.hostfile_available as u8);
+
+    out.0
+}
@@ -0,0 +1,73 @@
+use std::time::{Duration, Instant};
+
+use log::{error, info};
+
+use crate::admin::{add_module, parse_module, unlink_module};
+use crate::utils::module_listed;
+use crate::websocket::{StreamTarget, WS_SESSIONS};
+use crate::{CFG, REFLECTOR_CONNECTIONS};
+
+/// Link a module a stream client subscribed to, if it is not linked yet
+/// and `M17WEB_PROXY_ON_DEMAND` allows it.
+pub async fn request_module(reflector: &str, module: &str) {
+    if !module_listed(&CFG.on_demand, reflector, module) {
+        return;
+    }
+    // The wildcard lets any module name through
+    if parse_module(reflector, module).ok() != Some((reflector.to_string(), module.to_string())) {
+        return;
+    }
+    match add_module(reflector, module, true).await {
+        Ok(true) => info!("On demand: Linking {} Module {} for a stream client", reflector, module),
+        Ok(false) => {}
+        Err(e) => error!("On demand: Failed to add {} Module {}: {}", reflector, module, e),
+    }
+}
+
+/// Unlink on-demand modules without subscribers for `M17WEB_PROXY_ON_DEMAND_IDLE` seconds.
+pub async fn unlink_idle() {
+    let mut connections = REFLECTOR_CONNECTIONS.lock().await;
+    if !connections.iter().any(|c| c.on_demand) {
+        return;
+    }
+
+    let subscribed: Vec<StreamTarget> = WS_SESSIONS.lock().await.values()
+        .flat_map(|session| session.subscriptions.iter().cloned())
+        .collect();
+    let idle_timeout = Duration::from_secs(CFG.on_demand_idle);
+
+    let mut index = 0;
+    while index < connections.len() {
+        let connection = &mut connections[index];
+        if !connection.on_demand {
+            index += 1;
+            continue;
+        }
+
+        let in_use = subscribed.iter().any(|target| target.matches(&connection.reflector, &connection.module))
+            || has_listeners(&connection.reflector, &connection.module).await;
+        if in_use {
+            connection.idle_since = None;
+            index += 1;
+            continue;
+        }
+
+        let idle_since = *connection.idle_since.get_or_insert_with(Instant::now);
+        if idle_since.elapsed() >= idle_timeout {
+            info!("On demand: Unlinking {} Module {}, no subscribers for {} seconds", connection.reflector, connection.module, CFG.on_demand_idle);
+            unlink_module(&mut connections, index).await;
+        } else {
+            index += 1;
+        }
+    }
+}
+
+#[cfg(feature = "transcode")]
+async fn has_listeners(reflector: &str, module: &str) -> bool {
+    crate::listen::is_listened(reflector, module).await
+}
+
+#[cfg(not(feature = "transcode"))]
+async fn has_listeners(_reflector: &str, _module: &str) -> bool {
+    false
+}
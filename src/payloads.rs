@@ -1,4 +1,4 @@
-use crate::utils::encode_callsign;
+use crate::utils::{crc16, encode_callsign};
 
 pub fn create_conn_payload(method: String, callsign: String, module: String) -> Vec<u8> {
     let mut payload = vec![];
@@ -14,3 +14,46 @@ pub fn create_pong_payload(callsign: String) -> Vec<u8> {
     payload.extend_from_slice(encode_callsign(callsign.clone()).as_slice());
     payload
 }
+
+pub fn create_disc_payload(callsign: String) -> Vec<u8> {
+    let mut payload = vec![];
+    payload.extend_from_slice("DISC".as_bytes());
+    payload.extend_from_slice(encode_callsign(callsign).as_slice());
+    payload
+}
+
+pub fn create_lsd(dst: String, src: String, lsf_type: u16, meta: &[u8]) -> Vec<u8> {
+    let mut lsd = vec![];
+    lsd.extend_from_slice(encode_callsign(dst).as_slice());
+    lsd.extend_from_slice(encode_callsign(src).as_slice());
+    lsd.extend_from_slice(&lsf_type.to_be_bytes());
+    lsd.extend_from_slice(meta);
+    lsd.resize(28, 0);
+    lsd
+}
+
+pub fn create_stream_payload(stream_id: u16, lsd: &[u8], frame_number: u16, last: bool, data: &[u8]) -> Vec<u8> {
+    let mut payload = vec![];
+    payload.extend_from_slice("M17 ".as_bytes());
+    payload.extend_from_slice(&stream_id.to_be_bytes());
+    payload.extend_from_slice(lsd);
+    let frame_number = if last { frame_number | 0x8000 } else { frame_number & 0x7FFF };
+    payload.extend_from_slice(&frame_number.to_be_bytes());
+    payload.extend_from_slice(data);
+    let crc = crc16(&payload);
+    payload.extend_from_slice(&crc.to_be_bytes());
+    payload
+}
+
+pub fn create_packet_payload(lsd: &[u8], protocol: u8, data: &[u8]) -> Vec<u8> {
+    let mut payload = vec![];
+    payload.extend_from_slice("M17P".as_bytes());
+    payload.extend_from_slice(lsd);
+    payload.extend_from_slice(&crc16(lsd).to_be_bytes());
+    let mut packet = vec![protocol];
+    packet.extend_from_slice(data);
+    let crc = crc16(&packet);
+    payload.extend_from_slice(&packet);
+    payload.extend_from_slice(&crc.to_be_bytes());
+    payload
+}
@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::get_epoch;
+use crate::m17::StreamFrame;
+use crate::meta::GnssPosition;
+use crate::store::QsoRecord;
+
+/// A stream without frames for this long is considered ended even without end-of-stream bit.
+pub const QSO_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A single transmission on a module, identified by its stream ID.
+#[derive(Serialize, Clone, Debug)]
+pub struct QsoMeta {
+    pub stream_id: u16,
+    pub callsign: String,
+    pub destination: String,
+    pub can: u8,
+    /// Start of the transmission, seconds since epoch.
+    pub timestamp: u64,
+    /// Time of the last received frame, seconds since epoch.
+    pub last_frame: u64,
+    pub end: Option<u64>,
+    pub duration_ms: u64,
+    pub frames: u32,
+    pub text: Option<String>,
+    /// Last GNSS position reported during the QSO.
+    pub position: Option<GnssPosition>,
+    #[serde(skip)]
+    started: Instant,
+    #[serde(skip)]
+    last_frame_at: Instant,
+}
+
+/// Why a QSO ended.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum QsoEndReason {
+    /// The last frame had the end-of-stream bit set.
+    Eot,
+    /// No frames arrived within `QSO_TIMEOUT`.
+    Timeout,
+    /// The proxy shut down while the QSO was ongoing.
+    Shutdown,
+    /// The module was removed while the QSO was ongoing.
+    Unlinked,
+}
+
+/// Tracks the active streams of a module by stream ID.
+#[derive(Default)]
+pub struct QsoTracker {
+    active: HashMap<u16, QsoMeta>,
+    last: Option<QsoMeta>,
+}
+
+impl QsoTracker {
+    /// Account a stream frame. Returns the QSO if the frame started a new one
+    /// and the finished QSO if the frame carried the end-of-stream bit.
+    pub fn frame(&mut self, frame: &StreamFrame) -> (Option<QsoMeta>, Option<QsoMeta>) {
+        let now = Instant::now();
+        let epoch = get_epoch();
+        let mut started = None;
+
+        if !self.active.contains_key(&frame.stream_id) {
+            // Late duplicates of an already finished stream must not start a new QSO
+            let finished = self.last.as_ref().is_some_and(|last| {
+                last.stream_id == frame.stream_id && now.duration_since(last.last_frame_at) < QSO_TIMEOUT
+            });
+            if finished {
+                return (None, None);
+            }
+
+            let qso = QsoMeta {
+                stream_id: frame.stream_id,
+                callsign: frame.lsf.src.clone(),
+                destination: frame.lsf.dst.clone(),
+                can: frame.lsf.lsf_type.can,
+                timestamp: epoch.as_secs(),
+                last_frame: epoch.as_secs(),
+                end: None,
+                duration_ms: 0,
+                frames: 0,
+                text: None,
+                position: None,
+                started: now,
+                last_frame_at: now,
+            };
+            started = Some(qso.clone());
+            self.active.insert(frame.stream_id, qso);
+        }
+
+        let Some(qso) = self.active.get_mut(&frame.stream_id) else {
+            return (started, None);
+        };
+        qso.frames += 1;
+        qso.last_frame = epoch.as_secs();
+        qso.last_frame_at = now;
+        qso.duration_ms = now.duration_since(qso.started).as_millis() as u64;
+
+        let ended = if frame.last { self.finish(frame.stream_id) } else { None };
+        (started, ended)
+    }
+
+    /// Attach assembled status text to an active QSO.
+    pub fn set_text(&mut self, stream_id: u16, text: String) {
+        if let Some(qso) = self.active.get_mut(&stream_id) {
+            qso.text = Some(text);
+        }
+    }
+
+    /// Attach a reported GNSS position to an active QSO.
+    pub fn set_position(&mut self, stream_id: u16, position: GnssPosition) {
+        if let Some(qso) = self.active.get_mut(&stream_id) {
+            qso.position = Some(position);
+        }
+    }
+
+    /// End all QSOs that have not received a frame within `QSO_TIMEOUT`.
+    pub fn expire(&mut self) -> Vec<QsoMeta> {
+        let expired: Vec<u16> = self.active.values()
+            .filter(|qso| qso.last_frame_at.elapsed() > QSO_TIMEOUT)
+            .map(|qso| qso.stream_id)
+            .collect();
+        expired.into_iter().filter_map(|id| self.finish(id)).collect()
+    }
+
+    /// End all ongoing QSOs.
+    pub fn end_all(&mut self) -> Vec<QsoMeta> {
+        let active: Vec<u16> = self.active.keys().copied().collect();
+        active.into_iter().filter_map(|id| self.finish(id)).collect()
+    }
+
+    pub fn is_active(&self) -> bool {
+        !self.active.is_empty()
+    }
+
+    /// Active QSOs, oldest first.
+    pub fn active(&self) -> Vec<QsoMeta> {
+        let mut active: Vec<QsoMeta> = self.active.values().cloned().collect();
+        active.sort_by_key(|qso| qso.started);
+        active
+    }
+
+    /// The most recently started active QSO, or the last finished one.
+    pub fn latest(&self) -> Option<&QsoMeta> {
+        self.active.values()
+            .max_by_key(|qso| qso.started)
+            .or(self.last.as_ref())
+    }
+
+    fn finish(&mut self, stream_id: u16) -> Option<QsoMeta> {
+        let mut qso = self.active.remove(&stream_id)?;
+        qso.end = Some(qso.last_frame);
+        self.last = Some(qso.clone());
+        Some(qso)
+    }
+}
+
+impl QsoMeta {
+    pub fn to_record(&self, reflector: &str, module: &str) -> QsoRecord {
+        QsoRecord {
+            reflector: reflector.to_string(),
+            module: module.to_string(),
+            stream_id: self.stream_id,
+            src_call: self.callsign.clone(),
+            dst_call: self.destination.clone(),
+            start: self.timestamp,
+            end: self.end.unwrap_or(self.last_frame),
+            duration_ms: self.duration_ms,
+            frames: self.frames,
+            can: self.can,
+            text: self.text.clone(),
+            gnss: self.position.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::m17::voice_stream_type;
+    use crate::payloads::{create_lsd, create_stream_payload};
+
+    fn frame(stream_id: u16, src: &str, frame_number: u16, last: bool) -> StreamFrame {
+        let lsd = create_lsd("ALL".to_string(), src.to_string(), voice_stream_type(2), &[]);
+        StreamFrame::parse(&create_stream_payload(stream_id, &lsd, frame_number, last, &[0; 16])).unwrap()
+    }
+
+    #[test]
+    fn overlapping_streams() {
+        let mut tracker = QsoTracker::default();
+
+        let (started, ended) = tracker.frame(&frame(1, "N0CALL", 0, false));
+        assert_eq!(started.map(|qso| qso.callsign), Some("N0CALL".to_string()));
+        assert!(ended.is_none());
+        let (started, _) = tracker.frame(&frame(2, "M17XYZ", 0, false));
+        assert_eq!(started.map(|qso| (qso.stream_id, qso.destination, qso.can)), Some((2, "ALL".to_string(), 2)));
+
+        // Further frames neither start nor end a QSO
+        assert!(matches!(tracker.frame(&frame(1, "N0CALL", 1, false)), (None, None)));
+        assert_eq!(tracker.active().iter().map(|qso| qso.stream_id).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(tracker.latest().map(|qso| qso.stream_id), Some(2));
+
+        let (_, ended) = tracker.frame(&frame(1, "N0CALL", 2, true));
+        let ended = ended.unwrap();
+        assert_eq!((ended.stream_id, ended.frames), (1, 3));
+        assert!(ended.end.is_some());
+        assert!(tracker.is_active());
+
+        // A late duplicate of the finished stream is ignored
+        assert!(matches!(tracker.frame(&frame(1, "N0CALL", 2, true)), (None, None)));
+
+        let ended = tracker.end_all();
+        assert_eq!(ended.iter().map(|qso| qso.stream_id).collect::<Vec<_>>(), vec![2]);
+        assert!(!tracker.is_active());
+        assert_eq!(tracker.latest().map(|qso| qso.stream_id), Some(2));
+    }
+
+    #[test]
+    fn text_and_position_of_active_qsos() {
+        let mut tracker = QsoTracker::default();
+        tracker.frame(&frame(1, "N0CALL", 0, false));
+        tracker.set_text(1, "Hello".to_string());
+        tracker.set_text(9, "Unknown stream".to_string());
+
+        let (_, ended) = tracker.frame(&frame(1, "N0CALL", 1, true));
+        let record = ended.unwrap().to_record("M17-XOR", "A");
+        assert_eq!(record.text.as_deref(), Some("Hello"));
+        assert_eq!((record.reflector.as_str(), record.module.as_str(), record.src_call.as_str()), ("M17-XOR", "A", "N0CALL"));
+        assert!(record.gnss.is_none());
+    }
+}
@@ -0,0 +1,350 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime};
+
+use log::{error, info, warn};
+use serde::Serialize;
+
+use crate::qso::{QsoEndReason, QsoMeta};
+use crate::store::QsoRecord;
+use crate::transcode::{AudioFormat, Transcoder, SAMPLE_RATE};
+use crate::utils::module_listed;
+use crate::CFG;
+
+static RECORDER: OnceLock<mpsc::Sender<RecordEvent>> = OnceLock::new();
+
+/// Header of a `.c2` file as written by `c2enc`: magic, version 1.2, mode 3200, no flags.
+const C2_HEADER: [u8; 7] = [0xC0, 0xDE, 0xC2, 1, 2, 0, 0];
+/// How often expired recordings are cleaned up.
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(3600);
+/// How long shutdown waits for the recorder to finish writing.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+enum RecordEvent {
+    Start { reflector: String, module: String, qso: QsoMeta },
+    Audio { reflector: String, module: String, stream_id: u16, payload: Vec<u8> },
+    End { reflector: String, module: String, qso: QsoMeta, reason: QsoEndReason },
+    /// Sent on shutdown after the last QSOs were ended, acknowledged once all files are written.
+    Shutdown { done: mpsc::Sender<()> },
+}
+
+/// JSON sidecar written next to the audio files of a QSO.
+#[derive(Serialize)]
+struct RecordingMeta {
+    #[serde(flatten)]
+    qso: QsoRecord,
+    reason: QsoEndReason,
+    files: Vec<String>,
+}
+
+/// An ongoing recording of a single stream.
+struct Recording {
+    base: PathBuf,
+    c2: Option<BufWriter<File>>,
+    wav: Option<WavWriter>,
+    transcoder: Transcoder,
+}
+
+/// 8 kHz mono 16-bit WAV file, sizes are filled in when finished.
+struct WavWriter {
+    file: BufWriter<File>,
+    data_len: u32,
+}
+
+impl WavWriter {
+    fn create(path: &Path) -> io::Result<WavWriter> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(b"RIFF")?;
+        file.write_all(&36u32.to_le_bytes())?;
+        file.write_all(b"WAVEfmt ")?;
+        file.write_all(&16u32.to_le_bytes())?;
+        file.write_all(&1u16.to_le_bytes())?; // PCM
+        file.write_all(&1u16.to_le_bytes())?; // Mono
+        file.write_all(&SAMPLE_RATE.to_le_bytes())?;
+        file.write_all(&(SAMPLE_RATE * 2).to_le_bytes())?; // Byte rate
+        file.write_all(&2u16.to_le_bytes())?; // Block align
+        file.write_all(&16u16.to_le_bytes())?; // Bits per sample
+        file.write_all(b"data")?;
+        file.write_all(&0u32.to_le_bytes())?;
+        Ok(WavWriter { file, data_len: 0 })
+    }
+
+    fn write(&mut self, pcm: &[i16]) -> io::Result<()> {
+        for sample in pcm {
+            self.file.write_all(&sample.to_le_bytes())?;
+        }
+        self.data_len += pcm.len() as u32 * 2;
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(4))?;
+        self.file.write_all(&(36 + self.data_len).to_le_bytes())?;
+        self.file.seek(SeekFrom::Start(40))?;
+        self.file.write_all(&self.data_len.to_le_bytes())?;
+        self.file.flush()
+    }
+}
+
+impl Recording {
+    fn create(base: PathBuf, c2: bool, wav: bool) -> io::Result<Recording> {
+        if let Some(dir) = base.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let c2 = if c2 {
+            let mut file = BufWriter::new(File::create(base.with_extension("c2"))?);
+            file.write_all(&C2_HEADER)?;
+            Some(file)
+        } else {
+            None
+        };
+        let wav = if wav { Some(WavWriter::create(&base.with_extension("wav"))?) } else { None };
+        Ok(Recording { base, c2, wav, transcoder: Transcoder::default() })
+    }
+
+    fn write(&mut self, payload: &[u8]) -> io::Result<()> {
+        if let Some(c2) = &mut self.c2 {
+            c2.write_all(payload)?;
+        }
+        if let Some(wav) = &mut self.wav {
+            if let Some(pcm) = self.transcoder.decode(payload) {
+                wav.write(&pcm)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self, meta: RecordingMeta) -> io::Result<()> {
+        if let Some(mut c2) = self.c2 {
+            c2.flush()?;
+        }
+        if let Some(wav) = self.wav {
+            wav.finish()?;
+        }
+        fs::write(self.base.with_extension("json"), serde_json::to_vec_pretty(&meta).unwrap())
+    }
+
+    fn files(&self) -> Vec<String> {
+        let mut files = vec![];
+        if self.c2.is_some() {
+            files.push("c2");
+        }
+        if self.wav.is_some() {
+            files.push("wav");
+        }
+        files.into_iter()
+            .filter_map(|ext| self.base.with_extension(ext).file_name().map(|name| name.to_string_lossy().to_string()))
+            .collect()
+    }
+}
+
+/// Start the recorder if `M17WEB_PROXY_RECORD_DIR` is set.
+pub fn init_recorder() -> Result<(), String> {
+    if CFG.record_dir.is_empty() {
+        return Ok(());
+    }
+    let dir = PathBuf::from(&CFG.record_dir);
+    fs::create_dir_all(&dir).map_err(|e| format!("Failed to create recording directory {}: {}", CFG.record_dir, e))?;
+
+    let formats: Vec<&str> = CFG.record_format.split(',').map(str::trim).collect();
+    let c2 = formats.contains(&"c2");
+    let mut wav = formats.contains(&"wav");
+    if wav && !AudioFormat::Pcm16.available() {
+        warn!("Recorder: WAV recording requires the transcode feature, recording Codec2 only");
+        wav = false;
+    }
+    if !c2 && !wav {
+        return Err(format!("No usable recording format in M17WEB_PROXY_RECORD_FORMAT: {}", CFG.record_format));
+    }
+
+    let (tx, rx) = mpsc::channel();
+    std::thread::Builder::new()
+        .name("recorder".to_string())
+        .spawn(move || run(dir, rx, c2, wav))
+        .map_err(|e| format!("Failed to start recorder: {}", e))?;
+    let _ = RECORDER.set(tx);
+
+    info!("Recorder: Recording QSOs to {} ({})", CFG.record_dir, CFG.record_format);
+    Ok(())
+}
+
+fn send(event: RecordEvent) {
+    if let Some(tx) = RECORDER.get() {
+        let _ = tx.send(event);
+    }
+}
+
+pub fn record_start(reflector: &str, module: &str, qso: &QsoMeta) {
+    send(RecordEvent::Start { reflector: reflector.to_string(), module: module.to_string(), qso: qso.clone() });
+}
+
+pub fn record_audio(reflector: &str, module: &str, stream_id: u16, payload: &[u8]) {
+    if RECORDER.get().is_some() {
+        send(RecordEvent::Audio { reflector: reflector.to_string(), module: module.to_string(), stream_id, payload: payload.to_vec() });
+    }
+}
+
+pub fn record_end(reflector: &str, module: &str, qso: &QsoMeta, reason: QsoEndReason) {
+    send(RecordEvent::End { reflector: reflector.to_string(), module: module.to_string(), qso: qso.clone(), reason });
+}
+
+/// Wait for the recorder to write out everything queued so far, then stop it.
+pub async fn shutdown_recorder() {
+    let Some(tx) = RECORDER.get() else {
+        return;
+    };
+    let (done_tx, done_rx) = mpsc::channel();
+    if tx.send(RecordEvent::Shutdown { done: done_tx }).is_err() {
+        return;
+    }
+    let _ = tokio::task::spawn_blocking(move || done_rx.recv_timeout(SHUTDOWN_TIMEOUT)).await;
+}
+
+fn run(dir: PathBuf, rx: mpsc::Receiver<RecordEvent>, c2: bool, wav: bool) {
+    let mut recordings: HashMap<(String, String, u16), Recording> = HashMap::new();
+    // Size of the recording directory, kept up to date between the scans
+    let mut total = cleanup(&dir);
+
+    loop {
+        let event = match rx.recv_timeout(CLEANUP_INTERVAL) {
+            Ok(event) => event,
+            Err(RecvTimeoutError::Timeout) => {
+                total = cleanup(&dir);
+                continue;
+            }
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+
+        match event {
+            RecordEvent::Start { reflector, module, qso } => {
+                if !CFG.record_modules.is_empty() && !module_listed(&CFG.record_modules, &reflector, &module) {
+                    continue;
+                }
+                let callsign: String = qso.callsign.chars()
+                    .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+                    .collect();
+                let base = dir.join(&reflector).join(&module)
+                    .join(format!("{}_{:04x}_{}", qso.timestamp, qso.stream_id, callsign));
+                match Recording::create(base, c2, wav) {
+                    Ok(recording) => {
+                        recordings.insert((reflector, module, qso.stream_id), recording);
+                    }
+                    Err(e) => error!("Recorder: Failed to start recording of {}: {}", qso.callsign, e),
+                }
+            }
+            RecordEvent::Audio { reflector, module, stream_id, payload } => {
+                let key = (reflector, module, stream_id);
+                if let Some(recording) = recordings.get_mut(&key) {
+                    if let Err(e) = recording.write(&payload) {
+                        error!("Recorder: Failed to write {}: {}", recording.base.display(), e);
+                        recordings.remove(&key);
+                    }
+                }
+            }
+            RecordEvent::End { reflector, module, qso, reason } => {
+                let Some(recording) = recordings.remove(&(reflector.clone(), module.clone(), qso.stream_id)) else {
+                    continue;
+                };
+                let meta = RecordingMeta {
+                    qso: qso.to_record(&reflector, &module),
+                    reason,
+                    files: recording.files(),
+                };
+                let base = recording.base.clone();
+                match recording.finish(meta) {
+                    Ok(()) => info!("Recorder: Saved QSO of {} to {}", qso.callsign, base.display()),
+                    Err(e) => error!("Recorder: Failed to finish {}: {}", base.display(), e),
+                }
+                total += ["c2", "wav", "json"].iter()
+                    .filter_map(|ext| fs::metadata(base.with_extension(ext)).ok())
+                    .map(|metadata| metadata.len())
+                    .sum::<u64>();
+                if CFG.record_quota_mb > 0 && total > CFG.record_quota_mb * 1024 * 1024 {
+                    total = cleanup(&dir);
+                }
+            }
+            RecordEvent::Shutdown { done } => {
+                if !recordings.is_empty() {
+                    warn!("Recorder: Leaving {} unfinished recordings", recordings.len());
+                }
+                // Flush the open files before acknowledging
+                drop(recordings);
+                info!("Recorder: Stopped");
+                let _ = done.send(());
+                return;
+            }
+        }
+    }
+}
+
+/// A QSO recording: the audio files and JSON sidecar sharing a base name.
+struct RecordingFiles {
+    /// Paths and sizes.
+    files: Vec<(PathBuf, u64)>,
+    size: u64,
+    /// Newest modification time of its files.
+    modified: SystemTime,
+}
+
+/// Delete recordings older than `M17WEB_PROXY_RECORD_MAX_AGE_DAYS`, then the oldest ones
+/// until the directory fits into `M17WEB_PROXY_RECORD_QUOTA_MB`. All files of a recording
+/// are deleted together. Returns the size of the remaining recordings.
+fn cleanup(dir: &Path) -> u64 {
+    let mut files = vec![];
+    collect_files(dir, &mut files);
+
+    let mut grouped: HashMap<PathBuf, RecordingFiles> = HashMap::new();
+    for (path, size, modified) in files {
+        let recording = grouped.entry(path.with_extension("")).or_insert(RecordingFiles {
+            files: vec![],
+            size: 0,
+            modified,
+        });
+        recording.files.push((path, size));
+        recording.size += size;
+        recording.modified = recording.modified.max(modified);
+    }
+    let mut recordings: Vec<(PathBuf, RecordingFiles)> = grouped.into_iter().collect();
+    recordings.sort_by_key(|(_, recording)| recording.modified);
+
+    let max_age = Duration::from_secs(CFG.record_max_age_days * 86400);
+    let quota = CFG.record_quota_mb * 1024 * 1024;
+    let mut total: u64 = recordings.iter().map(|(_, recording)| recording.size).sum();
+
+    for (base, recording) in recordings {
+        let expired = CFG.record_max_age_days > 0
+            && recording.modified.elapsed().is_ok_and(|age| age > max_age);
+        let over_quota = quota > 0 && total > quota;
+        if !expired && !over_quota {
+            continue;
+        }
+        for (path, size) in recording.files {
+            match fs::remove_file(&path) {
+                Ok(()) => total -= size,
+                Err(e) => warn!("Recorder: Failed to delete {}: {}", path.display(), e),
+            }
+        }
+        info!("Recorder: Deleted {} ({})", base.display(), if expired { "expired" } else { "over quota" });
+    }
+    total
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<(PathBuf, u64, SystemTime)>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        if metadata.is_dir() {
+            collect_files(&entry.path(), files);
+        } else if let Ok(modified) = metadata.modified() {
+            files.push((entry.path(), metadata.len(), modified));
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{debug, warn};
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::task::JoinHandle;
+
+/// A datagram received from a reflector, tagged with the connection it arrived on.
+pub struct ReflectorFrame {
+    pub reflector: String,
+    pub module: String,
+    pub data: Vec<u8>,
+}
+
+/// Spawn a task that awaits datagrams on the given reflector socket and forwards
+/// them to the frame channel. Each reflector/module connection gets its own task,
+/// so a busy module can never starve the others.
+pub fn spawn_receiver(
+    reflector: String,
+    module: String,
+    socket: Arc<UdpSocket>,
+    tx: UnboundedSender<ReflectorFrame>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut buf = [0; 1024];
+        loop {
+            match socket.recv(&mut buf).await {
+                Ok(n) => {
+                    let frame = ReflectorFrame {
+                        reflector: reflector.clone(),
+                        module: module.clone(),
+                        data: buf[..n].to_vec(),
+                    };
+                    if tx.send(frame).is_err() {
+                        debug!("Frame channel closed, stopping receiver for {} Module {}", reflector, module);
+                        break;
+                    }
+                }
+                Err(e) => {
+                    // A connected UDP socket reports ICMP errors (e.g. connection refused)
+                    // on recv - back off briefly and keep listening, the reconnect logic
+                    // takes care of re-linking.
+                    warn!("Receive error on {} Module {}: {}", reflector, module, e);
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        }
+    })
+}
@@ -0,0 +1,105 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+use log::info;
+
+use crate::m17::StreamFrame;
+use crate::transcode::{AudioFormat, Transcoder};
+use crate::websocket::{send_stream_frame, Session, WsPayload};
+use crate::{CFG, REFLECTOR_CONNECTIONS};
+
+/// Longest transmission kept for replay, five minutes of 40 ms frames.
+const MAX_FRAMES: usize = 7500;
+/// Interval between two stream frames.
+const FRAME_INTERVAL: Duration = Duration::from_millis(40);
+
+/// The most recent transmissions of a module, bounded by `M17WEB_PROXY_REPLAY_TRANSMISSIONS`.
+#[derive(Default)]
+pub struct ReplayBuffer {
+    active: HashMap<u16, Vec<StreamFrame>>,
+    finished: VecDeque<Vec<StreamFrame>>,
+}
+
+impl ReplayBuffer {
+    /// Add a stream frame, finishing the transmission on its end-of-stream frame.
+    pub fn push(&mut self, frame: &StreamFrame) {
+        if CFG.replay_transmissions == 0 {
+            return;
+        }
+        // Late duplicates of a finished transmission
+        if self.finished.iter().any(|frames| frames.first().is_some_and(|f| f.stream_id == frame.stream_id)) {
+            return;
+        }
+
+        let frames = self.active.entry(frame.stream_id).or_default();
+        if frames.len() < MAX_FRAMES {
+            frames.push(frame.clone());
+        }
+        if frame.last {
+            self.finish(frame.stream_id);
+        }
+    }
+
+    /// Move a transmission to the replayable ones, e.g. after it timed out.
+    pub fn finish(&mut self, stream_id: u16) {
+        let Some(frames) = self.active.remove(&stream_id) else {
+            return;
+        };
+        self.finished.push_back(frames);
+        while self.finished.len() > CFG.replay_transmissions {
+            self.finished.pop_front();
+        }
+    }
+
+    /// The last `count` finished transmissions, oldest first.
+    fn last(&self, count: usize) -> Vec<Vec<StreamFrame>> {
+        let skip = self.finished.len().saturating_sub(count);
+        self.finished.iter().skip(skip).cloned().collect()
+    }
+}
+
+/// The last `count` transmissions of a module, or None if the module is not linked.
+pub async fn recent_transmissions(reflector: &str, module: &str, count: usize) -> Option<Vec<Vec<StreamFrame>>> {
+    REFLECTOR_CONNECTIONS.lock().await.iter()
+        .find(|c| c.reflector == reflector && c.module == module)
+        .map(|c| c.replay.last(count))
+}
+
+/// Send transmissions to a client at real-time pacing, flagged as replay.
+/// Returns false if the client went away.
+pub async fn replay(handle: &Session, reflector: &str, module: &str, format: AudioFormat, transmissions: Vec<Vec<StreamFrame>>) -> bool {
+    let mut transcoder = Transcoder::default();
+    let mut clock = tokio::time::interval(FRAME_INTERVAL);
+
+    for frame in transmissions.into_iter().flatten() {
+        clock.tick().await;
+
+        let pcm = if format == AudioFormat::Codec2 { None } else { transcoder.decode(&frame.payload) };
+        let opus = match &pcm {
+            Some(pcm) if format == AudioFormat::Opus => transcoder.encode_opus(pcm),
+            _ => vec![],
+        };
+
+        let payload = WsPayload {
+            reflector: reflector.to_string(),
+            module: module.to_string(),
+            src_call: frame.lsf.src.clone(),
+            dest_call: frame.lsf.dst.clone(),
+            stream_id: Some(frame.stream_id),
+            frame_number: Some(frame.frame_number),
+            packet_protocol: None,
+            gnss: frame.lsf.gnss(),
+            lsf: frame.lsf,
+            format,
+            c2_stream: if format == AudioFormat::Codec2 { frame.payload } else { vec![] },
+            pm_stream: vec![],
+            done: frame.last,
+            replay: true,
+        };
+        if !send_stream_frame(handle, &payload, pcm.as_deref(), &opus) {
+            info!("Replay of {} Module {} aborted, client went away", reflector, module);
+            return false;
+        }
+    }
+    true
+}
@@ -0,0 +1,83 @@
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use log::{error, info, warn};
+
+use crate::dht::{get_ref_address_from_dht, DhtNode};
+use crate::hostfile::{fetch_hostfile, resolve_from_hostfile, HostFileCache};
+use crate::{metrics, CFG};
+
+static RESOLVER: OnceLock<Resolver> = OnceLock::new();
+
+/// Sources for reflector addresses: the DHT first, the hostfile as fallback.
+struct Resolver {
+    dht_node: DhtNode,
+    hostfile_cache: Option<HostFileCache>,
+}
+
+/// Join the DHT and fetch the hostfile. Resolving is possible once this returns.
+pub async fn init_resolver() {
+    // Initialize the DHT node
+    let dht_identity_name = format!("M17WebProxy{}", std::process::id());
+    let dht_node = DhtNode::new(17171, &dht_identity_name)
+        .expect("Failed to create DHT node");
+
+    // Bootstrap into the ham-dht network
+    dht_node.bootstrap(&CFG.dht_bootstrap, &CFG.dht_port);
+
+    // Give the DHT node time to bootstrap and discover peers.
+    // While waiting, fetch the hostfile as a fallback source.
+    info!("DHT: Waiting for bootstrap to complete...");
+
+    let hostfile_future = fetch_hostfile(&CFG.hostfile_url);
+    let (hostfile_result, _) = tokio::join!(
+        hostfile_future,
+        tokio::time::sleep(Duration::from_secs(5))
+    );
+
+    metrics::hostfile_fetch(hostfile_result.is_ok());
+    let hostfile_cache: Option<HostFileCache> = match hostfile_result {
+        Ok(cache) => Some(cache),
+        Err(e) => {
+            warn!("Failed to fetch M17 hostfile: {} - hostfile fallback will not be available", e);
+            None
+        }
+    };
+
+    let _ = RESOLVER.set(Resolver { dht_node, hostfile_cache });
+}
+
+/// Stop the DHT node, if it was started.
+pub async fn shutdown_resolver() {
+    if let Some(resolver) = RESOLVER.get() {
+        resolver.dht_node.shutdown().await;
+    }
+}
+
+/// Resolve a reflector address: try DHT first, then hostfile fallback.
+pub async fn resolve(reflector_designator: &str) -> Option<String> {
+    let Some(resolver) = RESOLVER.get() else {
+        warn!("Cannot resolve {} before the DHT is initialized", reflector_designator);
+        return None;
+    };
+
+    let lookup_started = Instant::now();
+    let lookup = get_ref_address_from_dht(&resolver.dht_node, reflector_designator).await;
+    metrics::dht_lookup(lookup_started.elapsed(), lookup.is_ok());
+    match lookup {
+        Ok(addr) => {
+            info!("DHT: Resolved {} -> {}", reflector_designator, addr);
+            Some(addr)
+        }
+        Err(e) => {
+            warn!("DHT: Failed to resolve {}: {} - trying hostfile fallback", reflector_designator, e);
+
+            // Try hostfile fallback
+            let addr = resolve_from_hostfile(&resolver.hostfile_cache, reflector_designator);
+            if addr.is_none() {
+                error!("Failed to resolve {} from both DHT and hostfile - retrying later", reflector_designator);
+            }
+            addr
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use std::time::{Duration, Instant};
+
+use ezsockets::{CloseCode, CloseFrame};
+use log::{info, warn};
+
+use crate::qso::QsoEndReason;
+use crate::recorder::shutdown_recorder;
+use crate::resolver::shutdown_resolver;
+use crate::store::flush_store;
+use crate::websocket::WS_SESSIONS;
+use crate::REFLECTOR_CONNECTIONS;
+
+/// How long to wait for clients to acknowledge the close frame, and for the DHT to stop.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Leave all reflectors, close all clients and flush what is still buffered.
+pub async fn shutdown() {
+    for reflector_connection in REFLECTOR_CONNECTIONS.lock().await.iter_mut() {
+        reflector_connection.unlink(QsoEndReason::Shutdown).await;
+    }
+
+    let frame = CloseFrame {
+        code: CloseCode::Away,
+        reason: "Proxy shutting down".into(),
+    };
+    for session in WS_SESSIONS.lock().await.values() {
+        let _ = session.ws_session.handle.close(Some(frame.clone()));
+    }
+    // Sessions remove themselves once the close handshake is done
+    let started = Instant::now();
+    while !WS_SESSIONS.lock().await.is_empty() {
+        if started.elapsed() > SHUTDOWN_TIMEOUT {
+            warn!("{} WebSocket clients did not close in time", WS_SESSIONS.lock().await.len());
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    shutdown_recorder().await;
+    flush_store().await;
+
+    if tokio::time::timeout(SHUTDOWN_TIMEOUT, shutdown_resolver()).await.is_err() {
+        warn!("DHT: Shutdown timed out");
+    }
+
+    info!("Shutdown complete");
+}
@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+use serde::Serialize;
+
+use crate::qso::QSO_TIMEOUT;
+
+/// Nominal interval between two M17 stream frames in milliseconds.
+const FRAME_INTERVAL_MS: f64 = 40.0;
+/// Number of expected frames after which the bad link flag is re-evaluated.
+const WINDOW_FRAMES: u64 = 250;
+/// Share of lost or invalid frames in a window above which a link is flagged as bad.
+const BAD_LINK_RATIO: f64 = 0.05;
+
+/// Reception quality of a reflector link.
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct LinkStats {
+    pub frames_received: u64,
+    pub invalid_frames: u64,
+    pub frames_lost: u64,
+    /// Smoothed deviation of frame arrival times from the 40 ms frame clock, in milliseconds.
+    pub jitter_ms: f64,
+    /// Set when too many frames were lost or invalid during the last window.
+    pub bad_link: bool,
+    #[serde(skip)]
+    window_expected: u64,
+    #[serde(skip)]
+    window_bad: u64,
+    /// Last frame number and arrival time per stream ID, streams may overlap.
+    #[serde(skip)]
+    last_frames: HashMap<u16, (u16, Instant)>,
+}
+
+impl LinkStats {
+    /// Record a frame that failed length or CRC checks.
+    /// Returns true if the bad link flag changed.
+    pub fn record_invalid(&mut self) -> bool {
+        self.invalid_frames += 1;
+        self.window_expected += 1;
+        self.window_bad += 1;
+        self.evaluate_window()
+    }
+
+    /// Record a valid stream frame, detecting gaps in the frame numbering.
+    /// Returns true if the bad link flag changed.
+    pub fn record_frame(&mut self, stream_id: u16, frame_number: u16, last: bool) -> bool {
+        let now = Instant::now();
+        self.frames_received += 1;
+        self.window_expected += 1;
+
+        // Streams that ended without EOT
+        self.last_frames.retain(|_, (_, arrival)| now.duration_since(*arrival) < QSO_TIMEOUT);
+
+        if let Some((last_number, last_arrival)) = self.last_frames.get(&stream_id) {
+            let step = frame_number.wrapping_sub(*last_number) & 0x7FFF;
+            // Large steps are duplicates or reordered frames, not loss
+            if step > 1 && step < 0x4000 {
+                let lost = (step - 1) as u64;
+                self.frames_lost += lost;
+                self.window_expected += lost;
+                self.window_bad += lost;
+            }
+            if step > 0 && step < 0x4000 {
+                let elapsed = now.duration_since(*last_arrival).as_secs_f64() * 1000.0;
+                let deviation = (elapsed - FRAME_INTERVAL_MS * step as f64).abs();
+                self.jitter_ms += (deviation - self.jitter_ms) / 16.0;
+            }
+        }
+
+        if last {
+            self.last_frames.remove(&stream_id);
+        } else {
+            self.last_frames.insert(stream_id, (frame_number, now));
+        }
+        self.evaluate_window()
+    }
+
+    fn evaluate_window(&mut self) -> bool {
+        if self.window_expected < WINDOW_FRAMES {
+            return false;
+        }
+        let bad_link = self.window_bad as f64 / self.window_expected as f64 > BAD_LINK_RATIO;
+        self.window_expected = 0;
+        self.window_bad = 0;
+
+        let changed = bad_link != self.bad_link;
+        self.bad_link = bad_link;
+        changed
+    }
+}
@@ -0,0 +1,340 @@
+use std::sync::{Arc, Mutex, OnceLock};
+
+use log::{error, info};
+use rusqlite::{params, params_from_iter, Connection, Row};
+use serde::{Deserialize, Serialize};
+
+use crate::meta::GnssPosition;
+
+static STORE: OnceLock<Arc<dyn Store>> = OnceLock::new();
+
+/// Default number of rows returned by history queries.
+const DEFAULT_LIMIT: usize = 50;
+/// Upper bound for rows returned by a single history query.
+const MAX_LIMIT: usize = 1000;
+
+/// A finished QSO as kept in the persistent store.
+#[derive(Serialize, Clone, Debug)]
+pub struct QsoRecord {
+    pub reflector: String,
+    pub module: String,
+    pub stream_id: u16,
+    pub src_call: String,
+    pub dst_call: String,
+    pub start: u64,
+    pub end: u64,
+    pub duration_ms: u64,
+    pub frames: u32,
+    pub can: u8,
+    pub text: Option<String>,
+    pub gnss: Option<GnssPosition>,
+}
+
+/// A packet-mode message as kept in the persistent store.
+#[derive(Serialize, Clone, Debug)]
+pub struct MessageRecord {
+    pub reflector: String,
+    pub module: String,
+    pub src_call: String,
+    pub dst_call: String,
+    pub protocol: String,
+    pub text: Option<String>,
+    pub data: Vec<u8>,
+    pub timestamp: u64,
+}
+
+/// Most recent activity of a callsign.
+#[derive(Serialize, Clone, Debug)]
+pub struct LastHeard {
+    pub callsign: String,
+    pub reflector: String,
+    pub module: String,
+    pub timestamp: u64,
+    pub qsos: u64,
+}
+
+/// Filter for history queries. All fields are optional and combined with AND.
+#[derive(Deserialize, Default, Debug)]
+pub struct HistoryQuery {
+    pub callsign: Option<String>,
+    pub reflector: Option<String>,
+    pub module: Option<String>,
+    /// Only entries at or after this time, seconds since epoch.
+    pub from: Option<u64>,
+    /// Only entries at or before this time, seconds since epoch.
+    pub to: Option<u64>,
+    pub limit: Option<usize>,
+}
+
+impl HistoryQuery {
+    fn limit(&self) -> usize {
+        self.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT)
+    }
+
+    // Build the WHERE clause and its parameters for the given callsign and time columns
+    fn filter(&self, call_column: &str, time_column: &str) -> (String, Vec<rusqlite::types::Value>) {
+        let mut clauses = vec![];
+        let mut values: Vec<rusqlite::types::Value> = vec![];
+
+        if let Some(callsign) = &self.callsign {
+            clauses.push(format!("{} = ?", call_column));
+            values.push(callsign.to_uppercase().into());
+        }
+        if let Some(reflector) = &self.reflector {
+            clauses.push("reflector = ?".to_string());
+            values.push(reflector.clone().into());
+        }
+        if let Some(module) = &self.module {
+            clauses.push("module = ?".to_string());
+            values.push(module.clone().into());
+        }
+        if let Some(from) = self.from {
+            clauses.push(format!("{} >= ?", time_column));
+            values.push((from as i64).into());
+        }
+        if let Some(to) = self.to {
+            clauses.push(format!("{} <= ?", time_column));
+            values.push((to as i64).into());
+        }
+
+        let clause = if clauses.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", clauses.join(" AND "))
+        };
+        (clause, values)
+    }
+}
+
+/// Persistent storage backend for QSOs and messages.
+pub trait Store: Send + Sync {
+    fn record_qso(&self, qso: &QsoRecord) -> Result<(), String>;
+    fn record_message(&self, message: &MessageRecord) -> Result<(), String>;
+    fn last_heard(&self, limit: usize) -> Result<Vec<LastHeard>, String>;
+    fn qsos(&self, query: &HistoryQuery) -> Result<Vec<QsoRecord>, String>;
+    fn messages(&self, query: &HistoryQuery) -> Result<Vec<MessageRecord>, String>;
+    /// Make everything written so far durable, called on shutdown.
+    fn flush(&self) -> Result<(), String>;
+}
+
+/// Store backed by a SQLite database file.
+pub struct SqliteStore {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    pub fn open(path: &str) -> Result<SqliteStore, String> {
+        let conn = Connection::open(path).map_err(|e| format!("Failed to open database {}: {}", path, e))?;
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+            CREATE TABLE IF NOT EXISTS qsos (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                reflector TEXT NOT NULL,
+                module TEXT NOT NULL,
+                stream_id INTEGER NOT NULL,
+                src_call TEXT NOT NULL,
+                dst_call TEXT NOT NULL,
+                start_time INTEGER NOT NULL,
+                end_time INTEGER NOT NULL,
+                duration_ms INTEGER NOT NULL,
+                frames INTEGER NOT NULL,
+                can INTEGER NOT NULL,
+                text TEXT,
+                gnss TEXT
+            );
+            CREATE INDEX IF NOT EXISTS qsos_src_call ON qsos (src_call, end_time);
+            CREATE INDEX IF NOT EXISTS qsos_end_time ON qsos (end_time);
+            CREATE TABLE IF NOT EXISTS messages (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                reflector TEXT NOT NULL,
+                module TEXT NOT NULL,
+                src_call TEXT NOT NULL,
+                dst_call TEXT NOT NULL,
+                protocol TEXT NOT NULL,
+                text TEXT,
+                data BLOB NOT NULL,
+                timestamp INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS messages_src_call ON messages (src_call, timestamp);
+            CREATE INDEX IF NOT EXISTS messages_timestamp ON messages (timestamp);",
+        ).map_err(|e| format!("Failed to initialize database {}: {}", path, e))?;
+
+        Ok(SqliteStore { conn: Mutex::new(conn) })
+    }
+}
+
+fn qso_from_row(row: &Row) -> rusqlite::Result<QsoRecord> {
+    let gnss: Option<String> = row.get(11)?;
+    Ok(QsoRecord {
+        reflector: row.get(0)?,
+        module: row.get(1)?,
+        stream_id: row.get(2)?,
+        src_call: row.get(3)?,
+        dst_call: row.get(4)?,
+        start: row.get::<_, i64>(5)? as u64,
+        end: row.get::<_, i64>(6)? as u64,
+        duration_ms: row.get::<_, i64>(7)? as u64,
+        frames: row.get(8)?,
+        can: row.get(9)?,
+        text: row.get(10)?,
+        gnss: gnss.and_then(|json| serde_json::from_str(&json).ok()),
+    })
+}
+
+fn message_from_row(row: &Row) -> rusqlite::Result<MessageRecord> {
+    Ok(MessageRecord {
+        reflector: row.get(0)?,
+        module: row.get(1)?,
+        src_call: row.get(2)?,
+        dst_call: row.get(3)?,
+        protocol: row.get(4)?,
+        text: row.get(5)?,
+        data: row.get(6)?,
+        timestamp: row.get::<_, i64>(7)? as u64,
+    })
+}
+
+impl Store for SqliteStore {
+    fn record_qso(&self, qso: &QsoRecord) -> Result<(), String> {
+        let gnss = qso.gnss.as_ref().map(|position| serde_json::to_string(position).unwrap());
+        self.conn.lock().unwrap().execute(
+            "INSERT INTO qsos (reflector, module, stream_id, src_call, dst_call, start_time, end_time, duration_ms, frames, can, text, gnss)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+            params![
+                qso.reflector, qso.module, qso.stream_id, qso.src_call, qso.dst_call,
+                qso.start as i64, qso.end as i64, qso.duration_ms as i64, qso.frames, qso.can,
+                qso.text, gnss,
+            ],
+        ).map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    fn record_message(&self, message: &MessageRecord) -> Result<(), String> {
+        self.conn.lock().unwrap().execute(
+            "INSERT INTO messages (reflector, module, src_call, dst_call, protocol, text, data, timestamp)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                message.reflector, message.module, message.src_call, message.dst_call,
+                message.protocol, message.text, message.data, message.timestamp as i64,
+            ],
+        ).map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    fn last_heard(&self, limit: usize) -> Result<Vec<LastHeard>, String> {
+        let conn = self.conn.lock().unwrap();
+        // SQLite returns the bare columns of the row holding MAX(end_time)
+        let mut stmt = conn.prepare(
+            "SELECT src_call, reflector, module, MAX(end_time), COUNT(*) FROM qsos
+            GROUP BY src_call ORDER BY MAX(end_time) DESC LIMIT ?1",
+        ).map_err(|e| e.to_string())?;
+        let rows = stmt.query_map(params![limit.min(MAX_LIMIT) as i64], |row| {
+            Ok(LastHeard {
+                callsign: row.get(0)?,
+                reflector: row.get(1)?,
+                module: row.get(2)?,
+                timestamp: row.get::<_, i64>(3)? as u64,
+                qsos: row.get::<_, i64>(4)? as u64,
+            })
+        }).map_err(|e| e.to_string())?;
+        rows.collect::<Result<_, _>>().map_err(|e| e.to_string())
+    }
+
+    fn qsos(&self, query: &HistoryQuery) -> Result<Vec<QsoRecord>, String> {
+        let (clause, mut values) = query.filter("src_call", "end_time");
+        values.push((query.limit() as i64).into());
+
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT reflector, module, stream_id, src_call, dst_call, start_time, end_time, duration_ms, frames, can, text, gnss
+            FROM qsos {} ORDER BY end_time DESC LIMIT ?",
+            clause,
+        )).map_err(|e| e.to_string())?;
+        let rows = stmt.query_map(params_from_iter(values), qso_from_row).map_err(|e| e.to_string())?;
+        rows.collect::<Result<_, _>>().map_err(|e| e.to_string())
+    }
+
+    fn messages(&self, query: &HistoryQuery) -> Result<Vec<MessageRecord>, String> {
+        let (clause, mut values) = query.filter("src_call", "timestamp");
+        values.push((query.limit() as i64).into());
+
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT reflector, module, src_call, dst_call, protocol, text, data, timestamp
+            FROM messages {} ORDER BY timestamp DESC LIMIT ?",
+            clause,
+        )).map_err(|e| e.to_string())?;
+        let rows = stmt.query_map(params_from_iter(values), message_from_row).map_err(|e| e.to_string())?;
+        rows.collect::<Result<_, _>>().map_err(|e| e.to_string())
+    }
+
+    fn flush(&self) -> Result<(), String> {
+        // Move the WAL into the database file so it is complete on its own
+        self.conn.lock().unwrap()
+            .execute_batch("PRAGMA wal_checkpoint(TRUNCATE);")
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Open the store configured by `M17WEB_PROXY_DATABASE`. An empty path disables persistence.
+pub fn init_store(path: &str) -> Result<(), String> {
+    if path.is_empty() {
+        info!("Store: No database configured, history will not be persisted");
+        return Ok(());
+    }
+    let store = SqliteStore::open(path)?;
+    info!("Store: Using SQLite database {}", path);
+    let _ = STORE.set(Arc::new(store));
+    Ok(())
+}
+
+pub fn store() -> Option<Arc<dyn Store>> {
+    STORE.get().cloned()
+}
+
+/// Persist a finished QSO in the background.
+pub fn record_qso(qso: QsoRecord) {
+    if let Some(store) = store() {
+        tokio::task::spawn_blocking(move || {
+            if let Err(e) = store.record_qso(&qso) {
+                error!("Store: Failed to record QSO of {}: {}", qso.src_call, e);
+            }
+        });
+    }
+}
+
+/// Persist a packet message in the background.
+pub fn record_message(message: MessageRecord) {
+    if let Some(store) = store() {
+        tokio::task::spawn_blocking(move || {
+            if let Err(e) = store.record_message(&message) {
+                error!("Store: Failed to record message of {}: {}", message.src_call, e);
+            }
+        });
+    }
+}
+
+/// Flush the store on shutdown.
+pub async fn flush_store() {
+    if let Some(store) = store() {
+        match tokio::task::spawn_blocking(move || store.flush()).await {
+            Ok(Ok(())) => info!("Store: Flushed"),
+            Ok(Err(e)) => error!("Store: Failed to flush: {}", e),
+            Err(e) => error!("Store: Failed to flush: {}", e),
+        }
+    }
+}
+
+/// Run a query against the store on the blocking thread pool.
+pub async fn query<T, F>(f: F) -> Result<T, String>
+where
+    T: Send + 'static,
+    F: FnOnce(&dyn Store) -> Result<T, String> + Send + 'static,
+{
+    let Some(store) = store() else {
+        return Err("History is not available, no database configured".to_string());
+    };
+    tokio::task::spawn_blocking(move || f(store.as_ref()))
+        .await
+        .map_err(|e| e.to_string())?
+}
@@ -0,0 +1,194 @@
+use serde::{Deserialize, Serialize};
+
+/// Sample rate of decoded Codec2 audio.
+pub const SAMPLE_RATE: u32 = 8000;
+/// Codec2 3200 bytes per 20 ms frame.
+#[cfg(feature = "transcode")]
+const C2_BYTES_PER_FRAME: usize = 8;
+/// Samples per 20 ms frame at 8 kHz, for both Codec2 and Opus.
+#[cfg(feature = "transcode")]
+pub const SAMPLES_PER_FRAME: usize = 160;
+
+/// Audio format delivered to a stream client.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AudioFormat {
+    /// Raw Codec2 3200 in `c2_stream`, decoded by the client.
+    #[default]
+    Codec2,
+    /// 8 kHz 16-bit little-endian PCM as binary messages.
+    Pcm16,
+    /// One binary message per 20 ms Opus packet.
+    Opus,
+}
+
+impl AudioFormat {
+    pub fn parse(name: &str) -> Option<AudioFormat> {
+        match name {
+            "codec2" => Some(AudioFormat::Codec2),
+            "pcm16" => Some(AudioFormat::Pcm16),
+            "opus" => Some(AudioFormat::Opus),
+            _ => None,
+        }
+    }
+
+    /// Whether this build can deliver the format. Decoding requires the `transcode` feature.
+    pub fn available(self) -> bool {
+        self == AudioFormat::Codec2 || cfg!(feature = "transcode")
+    }
+}
+
+/// Codec2 decoder and Opus encoder of a module, created on first use.
+#[derive(Default)]
+pub struct Transcoder {
+    #[cfg(feature = "transcode")]
+    decoder: Option<ffi::Codec2>,
+    #[cfg(feature = "transcode")]
+    encoder: Option<ffi::OpusEncoder>,
+}
+
+impl Transcoder {
+    /// Decode the Codec2 3200 payload of a stream frame to 8 kHz PCM.
+    #[cfg(feature = "transcode")]
+    pub fn decode(&mut self, c2: &[u8]) -> Option<Vec<i16>> {
+        if self.decoder.is_none() {
+            self.decoder = ffi::Codec2::new();
+        }
+        let decoder = self.decoder.as_mut()?;
+
+        let mut pcm = vec![0i16; c2.len() / C2_BYTES_PER_FRAME * SAMPLES_PER_FRAME];
+        for (bits, speech) in c2.chunks_exact(C2_BYTES_PER_FRAME).zip(pcm.chunks_exact_mut(SAMPLES_PER_FRAME)) {
+            decoder.decode(bits, speech);
+        }
+        Some(pcm)
+    }
+
+    #[cfg(not(feature = "transcode"))]
+    pub fn decode(&mut self, _c2: &[u8]) -> Option<Vec<i16>> {
+        None
+    }
+
+    /// Encode 8 kHz PCM to Opus, one packet per 20 ms.
+    #[cfg(feature = "transcode")]
+    pub fn encode_opus(&mut self, pcm: &[i16]) -> Vec<Vec<u8>> {
+        if self.encoder.is_none() {
+            self.encoder = ffi::OpusEncoder::new();
+        }
+        let Some(encoder) = self.encoder.as_mut() else {
+            return vec![];
+        };
+        pcm.chunks_exact(SAMPLES_PER_FRAME).filter_map(|frame| encoder.encode(frame)).collect()
+    }
+
+    #[cfg(not(feature = "transcode"))]
+    pub fn encode_opus(&mut self, _pcm: &[i16]) -> Vec<Vec<u8>> {
+        vec![]
+    }
+}
+
+/// Little-endian bytes of PCM samples, as sent to clients.
+pub fn pcm_bytes(pcm: &[i16]) -> Vec<u8> {
+    pcm.iter().flat_map(|sample| sample.to_le_bytes()).collect()
+}
+
+/// Bindings to the system libcodec2 and libopus.
+#[cfg(feature = "transcode")]
+mod ffi {
+    use libc::{c_int, c_short, c_uchar, c_void};
+    use log::error;
+
+    use super::{SAMPLES_PER_FRAME, SAMPLE_RATE};
+
+    const CODEC2_MODE_3200: c_int = 0;
+    const OPUS_APPLICATION_VOIP: c_int = 2048;
+    const OPUS_SET_BITRATE_REQUEST: c_int = 4002;
+    /// Opus bitrate for 8 kHz voice.
+    const OPUS_BITRATE: c_int = 16000;
+    /// Largest Opus packet we accept from the encoder.
+    const OPUS_MAX_PACKET: usize = 400;
+
+    #[link(name = "codec2")]
+    extern "C" {
+        fn codec2_create(mode: c_int) -> *mut c_void;
+        fn codec2_destroy(state: *mut c_void);
+        fn codec2_decode(state: *mut c_void, speech_out: *mut c_short, bits: *const c_uchar);
+    }
+
+    #[link(name = "opus")]
+    extern "C" {
+        fn opus_encoder_create(fs: i32, channels: c_int, application: c_int, error: *mut c_int) -> *mut c_void;
+        fn opus_encoder_destroy(state: *mut c_void);
+        fn opus_encoder_ctl(state: *mut c_void, request: c_int, ...) -> c_int;
+        fn opus_encode(state: *mut c_void, pcm: *const i16, frame_size: c_int, data: *mut c_uchar, max_data_bytes: i32) -> i32;
+    }
+
+    pub struct Codec2 {
+        state: *mut c_void,
+    }
+
+    // The decoder state is only ever used by the task owning the Transcoder
+    unsafe impl Send for Codec2 {}
+
+    impl Codec2 {
+        pub fn new() -> Option<Codec2> {
+            let state = unsafe { codec2_create(CODEC2_MODE_3200) };
+            if state.is_null() {
+                error!("Failed to create Codec2 decoder");
+                return None;
+            }
+            Some(Codec2 { state })
+        }
+
+        /// Decode one 8 byte Codec2 3200 frame into 160 samples.
+        pub fn decode(&mut self, bits: &[u8], speech: &mut [i16]) {
+            assert!(bits.len() == 8 && speech.len() == SAMPLES_PER_FRAME);
+            unsafe { codec2_decode(self.state, speech.as_mut_ptr(), bits.as_ptr()) }
+        }
+    }
+
+    impl Drop for Codec2 {
+        fn drop(&mut self) {
+            unsafe { codec2_destroy(self.state) }
+        }
+    }
+
+    pub struct OpusEncoder {
+        state: *mut c_void,
+    }
+
+    // The encoder state is only ever used by the task owning the Transcoder
+    unsafe impl Send for OpusEncoder {}
+
+    impl OpusEncoder {
+        pub fn new() -> Option<OpusEncoder> {
+            let mut err: c_int = 0;
+            let state = unsafe { opus_encoder_create(SAMPLE_RATE as i32, 1, OPUS_APPLICATION_VOIP, &mut err) };
+            if state.is_null() || err != 0 {
+                error!("Failed to create Opus encoder: {}", err);
+                return None;
+            }
+            unsafe { opus_encoder_ctl(state, OPUS_SET_BITRATE_REQUEST, OPUS_BITRATE) };
+            Some(OpusEncoder { state })
+        }
+
+        /// Encode one 20 ms frame of 160 samples.
+        pub fn encode(&mut self, pcm: &[i16]) -> Option<Vec<u8>> {
+            let mut packet = vec![0u8; OPUS_MAX_PACKET];
+            let len = unsafe {
+                opus_encode(self.state, pcm.as_ptr(), pcm.len() as c_int, packet.as_mut_ptr(), packet.len() as i32)
+            };
+            if len < 0 {
+                error!("Opus encoding failed: {}", len);
+                return None;
+            }
+            packet.truncate(len as usize);
+            Some(packet)
+        }
+    }
+
+    impl Drop for OpusEncoder {
+        fn drop(&mut self) {
+            unsafe { opus_encoder_destroy(self.state) }
+        }
+    }
+}
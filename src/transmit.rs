@@ -0,0 +1,226 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use lazy_static::lazy_static;
+use log::{error, info, warn};
+use rand::Rng;
+use tokio::net::UdpSocket;
+use tokio::sync::Mutex;
+use tokio::time::MissedTickBehavior;
+
+use crate::link::LinkState;
+use crate::m17::{data_packet_type, voice_stream_type, PacketProtocol};
+use crate::payloads::{create_lsd, create_packet_payload, create_stream_payload};
+use crate::utils::{module_listed, secret_eq};
+use crate::websocket::SessionID;
+use crate::{CFG, REFLECTOR_CONNECTIONS};
+
+lazy_static! {
+    static ref TRANSMISSIONS: Mutex<HashMap<SessionID, Transmission>> = Mutex::new(HashMap::new());
+    static ref LAST_SMS: Mutex<HashMap<String, Instant>> = Mutex::new(HashMap::new());
+}
+
+/// Codec2 3200 encoding of silence, used to pad the final frame of a transmission.
+const C2_SILENCE: [u8; 8] = [0x01, 0x00, 0x09, 0x43, 0x9C, 0xE4, 0x21, 0x08];
+/// Codec2 3200 bytes carried by one M17 stream frame (two 20 ms Codec2 frames).
+const C2_FRAME_BYTES: usize = 16;
+/// Stream frames are sent at the rate they are played back.
+const FRAME_INTERVAL: Duration = Duration::from_millis(40);
+/// At most 2 seconds of audio are buffered per transmission.
+const MAX_BUFFER_BYTES: usize = 50 * C2_FRAME_BYTES;
+/// A transmission without audio for this long is closed with an end-of-stream frame.
+const TX_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Longest SMS text fitting into a single M17 packet, leaving room for the
+/// protocol byte, NUL terminator and CRC.
+const SMS_MAX_LEN: usize = 818;
+
+/// An ongoing transmission of a WebSocket client towards a reflector module.
+struct Transmission {
+    reflector: String,
+    module: String,
+    callsign: String,
+    stream_id: u16,
+    lsd: Vec<u8>,
+    frame_number: u16,
+    buffer: Vec<u8>,
+    last_audio: Instant,
+    /// The client unkeyed, end the stream once the buffer is sent.
+    ending: bool,
+}
+
+impl Transmission {
+    fn new(callsign: &str, reflector: &str, module: &str) -> Transmission {
+        Transmission {
+            reflector: reflector.to_string(),
+            module: module.to_string(),
+            callsign: callsign.to_string(),
+            stream_id: rand::rng().random_range(1..=u16::MAX),
+            lsd: create_lsd("ALL".to_string(), callsign.to_string(), voice_stream_type(CFG.tx_can), &[]),
+            frame_number: 0,
+            buffer: vec![],
+            last_audio: Instant::now(),
+            ending: false,
+        }
+    }
+
+    fn next_packet(&mut self, data: &[u8], last: bool) -> Vec<u8> {
+        let packet = create_stream_payload(self.stream_id, &self.lsd, self.frame_number, last, data);
+        self.frame_number = (self.frame_number + 1) & 0x7FFF;
+        packet
+    }
+}
+
+/// Whether the given module is configured for transmitting (linked with CONN instead of LSTN).
+pub fn tx_enabled(reflector: &str, module: &str) -> bool {
+    module_listed(&CFG.tx_modules, reflector, module)
+}
+
+/// Check a callsign/token pair against the configured TX users.
+pub fn check_credentials(callsign: &str, token: &str) -> bool {
+    !token.is_empty() && CFG.tx_users.split(',')
+        .filter_map(|entry| entry.split_once(':'))
+        .any(|(user, secret)| user.eq_ignore_ascii_case(callsign) && secret_eq(secret, token))
+}
+
+/// Queue Codec2 3200 audio of a session for transmission, keying up on the first call.
+/// The buffered audio is sent as one M17 stream frame of 16 bytes every 40 ms.
+pub async fn transmit_audio(session: SessionID, callsign: &str, reflector: &str, module: &str, data: &[u8]) -> Result<(), String> {
+    let Some((socket, state)) = tx_target(reflector, module).await else {
+        return Err(format!("Transmitting on {} Module {} is not enabled", reflector, module));
+    };
+
+    let mut transmissions = TRANSMISSIONS.lock().await;
+
+    // The client switched modules mid-transmission - close the old stream first
+    if transmissions.get(&session).is_some_and(|tx| tx.reflector != reflector || tx.module != module) {
+        if let Some(tx) = transmissions.remove(&session) {
+            finish(tx).await;
+        }
+    }
+
+    let tx = match transmissions.entry(session) {
+        Entry::Occupied(entry) => entry.into_mut(),
+        Entry::Vacant(entry) => {
+            if state != LinkState::Linked {
+                return Err(format!("{} Module {} is not linked", reflector, module));
+            }
+            info!("{} keyed up on {} Module {} from WS_CONNECTION {}", callsign, reflector, module, session);
+            let tx = Transmission::new(callsign, reflector, module);
+            tokio::spawn(pace(session, tx.stream_id, socket));
+            entry.insert(tx)
+        }
+    };
+    if tx.buffer.len() + data.len() > MAX_BUFFER_BYTES {
+        return Err("Audio is sent faster than real time, buffer full".to_string());
+    }
+    tx.buffer.extend_from_slice(data);
+    tx.last_audio = Instant::now();
+    Ok(())
+}
+
+/// Unkey a session: its transmission ends with an end-of-stream frame once the buffered audio is sent.
+pub async fn end_transmission(session: SessionID) {
+    if let Some(tx) = TRANSMISSIONS.lock().await.get_mut(&session) {
+        tx.ending = true;
+    }
+}
+
+/// Send the buffered audio of a transmission at playback rate. The stream is closed
+/// once the buffer ran empty and the client unkeyed or sent no audio within the timeout.
+async fn pace(session: SessionID, stream_id: u16, socket: Arc<UdpSocket>) {
+    let mut interval = tokio::time::interval(FRAME_INTERVAL);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+        let mut transmissions = TRANSMISSIONS.lock().await;
+        // Gone or replaced by a transmission on another module
+        let Some(tx) = transmissions.get_mut(&session).filter(|tx| tx.stream_id == stream_id) else {
+            return;
+        };
+
+        if tx.buffer.len() >= C2_FRAME_BYTES {
+            let frame: Vec<u8> = tx.buffer.drain(..C2_FRAME_BYTES).collect();
+            let packet = tx.next_packet(&frame, false);
+            if let Err(e) = socket.send(&packet).await {
+                error!("Failed to send stream frame to {}: {}", tx.reflector, e);
+            }
+        } else if tx.ending || tx.last_audio.elapsed() > TX_TIMEOUT {
+            if !tx.ending {
+                warn!("Transmission of {} on {} Module {} timed out", tx.callsign, tx.reflector, tx.module);
+            }
+            let tx = transmissions.remove(&session).unwrap();
+            drop(transmissions);
+            finish(tx).await;
+            return;
+        }
+    }
+}
+
+/// Send an SMS packet to a reflector module as the given callsign.
+/// Each callsign may send one message per `M17WEB_PROXY_SMS_INTERVAL` seconds.
+pub async fn send_sms(callsign: &str, destination: &str, reflector: &str, module: &str, message: &str) -> Result<(), String> {
+    if message.is_empty() || message.len() > SMS_MAX_LEN {
+        return Err(format!("Message must be between 1 and {} bytes", SMS_MAX_LEN));
+    }
+
+    let Some((socket, state)) = tx_target(reflector, module).await else {
+        return Err(format!("Transmitting on {} Module {} is not enabled", reflector, module));
+    };
+    if state != LinkState::Linked {
+        return Err(format!("{} Module {} is not linked", reflector, module));
+    }
+
+    // Held until the packet is sent, so only sent messages count against the interval
+    let mut last_sms = LAST_SMS.lock().await;
+    let interval = Duration::from_secs(CFG.sms_interval);
+    if let Some(sent) = last_sms.get(callsign) {
+        if sent.elapsed() < interval {
+            return Err(format!("Rate limited, try again in {} seconds", (interval - sent.elapsed()).as_secs() + 1));
+        }
+    }
+
+    let lsd = create_lsd(destination.to_string(), callsign.to_string(), data_packet_type(CFG.tx_can), &[]);
+    let mut data = message.as_bytes().to_vec();
+    data.push(0);
+
+    let packet = create_packet_payload(&lsd, PacketProtocol::Sms.id(), &data);
+    socket.send(&packet).await.map_err(|e| format!("Failed to send packet: {}", e))?;
+    last_sms.insert(callsign.to_string(), Instant::now());
+
+    info!("{} sent SMS to {} on {} Module {}: {}", callsign, destination, reflector, module, message);
+    Ok(())
+}
+
+async fn finish(mut tx: Transmission) {
+    // Pad the remaining audio with silence to fill the last frame, audio beyond it
+    // is dropped when the stream is cut short
+    let mut data = std::mem::take(&mut tx.buffer);
+    data.truncate(data.len().min(C2_FRAME_BYTES));
+    data.truncate(data.len() - data.len() % C2_SILENCE.len());
+    while data.len() < C2_FRAME_BYTES {
+        data.extend_from_slice(&C2_SILENCE);
+    }
+
+    let packet = tx.next_packet(&data, true);
+    match tx_target(&tx.reflector, &tx.module).await {
+        Some((socket, _)) => {
+            if let Err(e) = socket.send(&packet).await {
+                error!("Failed to send end of stream to {}: {}", tx.reflector, e);
+            }
+        }
+        None => warn!("Cannot end transmission, {} Module {} is gone", tx.reflector, tx.module),
+    }
+
+    info!("{} unkeyed on {} Module {} after {} frames", tx.callsign, tx.reflector, tx.module, tx.frame_number);
+}
+
+/// Socket and link state of a module enabled for transmitting.
+async fn tx_target(reflector: &str, module: &str) -> Option<(Arc<UdpSocket>, LinkState)> {
+    REFLECTOR_CONNECTIONS.lock().await.iter()
+        .find(|c| c.reflector == reflector && c.module == module && c.transmit)
+        .map(|c| (c.socket.clone(), c.link.state))
+}
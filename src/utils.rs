@@ -9,7 +9,7 @@ pub fn decode_callsign(encoded: &[u8]) -> String {
     let callsign: &mut String = &mut "".to_string();
 
     if encoded[..6].iter().all(|&b| b == 0xFF) {
-        *callsign = "ALL".to_owned()
+        return "ALL".to_owned();
     }
 
     let mut enc: u64 = (encoded[0] as u64) << 40
@@ -20,7 +20,7 @@ pub fn decode_callsign(encoded: &[u8]) -> String {
         | (encoded[5] as u64);
 
     if enc >= 262144000000000 { // 40^9
-        *callsign = "".to_owned()
+        return "".to_owned();
     }
 
     let digits = CHARSET.to_string().into_bytes();
@@ -37,7 +37,8 @@ pub fn encode_callsign(callsign: String) -> Vec<u8> {
     let encoded: &mut [u8] = &mut [0;6];
 
     if callsign == "ALL" || callsign == " ALL      " {
-        encoded[..].fill(0xFF)
+        encoded[..].fill(0xFF);
+        return encoded.to_owned();
     }
 
     let len = callsign.trim().len().min(9);
@@ -58,7 +59,37 @@ pub fn encode_callsign(callsign: String) -> Vec<u8> {
     encoded[2] = ((enc >> 24) & 0xff) as u8;
     encoded[3] = ((enc >> 16) & 0xff) as u8;
     encoded[4] = ((enc >>  8) & 0xff) as u8;
-    encoded[5] = ((enc >>  0) & 0xff) as u8;
+    encoded[5] = (enc & 0xff) as u8;
 
     encoded.to_owned()
 }
+
+// M17 CRC-16 (polynomial 0x5935, initial value 0xFFFF)
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for byte in data {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x5935
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+// Whether a module is part of a list in subscription format (Designator_Modules,Designator_Modules,...)
+// `*` as modules matches all modules of the reflector.
+pub fn module_listed(list: &str, reflector: &str, module: &str) -> bool {
+    list.split(',').any(|entry| {
+        let mut parts = entry.split('_');
+        parts.next() == Some(reflector) && parts.next().is_some_and(|modules| modules == "*" || modules.contains(module))
+    })
+}
+
+// Compare secrets in constant time, so the position of the first mismatch cannot be timed
+pub fn secret_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
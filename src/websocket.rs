@@ -1,30 +1,109 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
 use async_trait::async_trait;
-use ezsockets::{CloseFrame, Error, Request, Socket, Utf8Bytes};
+use ezsockets::{CloseCode, CloseFrame, Error, Request, Socket, Utf8Bytes};
 use lazy_static::lazy_static;
 use log::{info, warn};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, Serializer};
 use tokio::sync::Mutex;
-use crate::{get_module_infos, MsgData};
+use crate::{add_message, get_epoch, message_page, metrics, PositionReport, REFLECTOR_CONNECTIONS};
+use crate::events::attach_info_client;
+use crate::link::LinkState;
+use crate::ondemand::request_module;
+use crate::m17::{Lsf, PacketProtocol};
+use crate::messages::{MessagePage, MsgData};
+use crate::meta::GnssPosition;
+use crate::qso::{QsoEndReason, QsoMeta};
+use crate::replay::{recent_transmissions, replay};
+use crate::stats::LinkStats;
+use crate::transcode::{pcm_bytes, AudioFormat};
+use crate::store::{query, record_message, HistoryQuery, LastHeard, MessageRecord, QsoRecord};
+use crate::transmit::{check_credentials, end_transmission, send_sms, transmit_audio};
 
 lazy_static! {
-    pub static ref WS_SESSIONS: Mutex<Vec<M17ClientSession>> = Mutex::new(vec![]);
+    pub static ref WS_SESSIONS: Mutex<HashMap<SessionID, M17ClientSession>> = Mutex::new(HashMap::new());
 }
 
-type SessionID = u16;
-type Session = ezsockets::Session<SessionID, ()>;
+/// Source of session IDs, unique for the lifetime of the process.
+static NEXT_SESSION_ID: AtomicU64 = AtomicU64::new(1);
+
+pub(crate) type SessionID = u64;
+pub(crate) type Session = ezsockets::Session<SessionID, ()>;
 
 pub struct M17ClientServer {}
 
 pub struct WebSocketClientSession {
     pub(crate) handle: Session,
-    id: SessionID,
+    pub(crate) id: SessionID,
 }
 
 pub struct M17ClientSession {
     pub(crate) ws_session: WebSocketClientSession,
-    pub(crate) subscription: ClientSubscription,
+    /// Modules streamed to this client, empty for info clients.
+    pub(crate) subscriptions: BTreeSet<StreamTarget>,
     pub(crate) info_connection: bool,
+    pub(crate) tx_callsign: Option<String>,
+    /// Audio format of the stream frames sent to this client.
+    pub(crate) format: AudioFormat,
+    pub(crate) remote_address: SocketAddr,
+    pub(crate) user_agent: Option<String>,
+    /// Epoch seconds of the connect.
+    pub(crate) connected_at: u64,
+}
+
+impl M17ClientSession {
+    /// Whether frames of the module go to this client.
+    pub(crate) fn subscribed(&self, reflector: &str, module: &str) -> bool {
+        !self.info_connection && self.subscriptions.iter().any(|target| target.matches(reflector, module))
+    }
+
+    /// The module the client is subscribed to, if it is exactly one.
+    fn single_module(&self) -> Option<&StreamTarget> {
+        match self.subscriptions.first() {
+            Some(target) if self.subscriptions.len() == 1 && !target.is_wildcard() => Some(target),
+            _ => None,
+        }
+    }
+}
+
+/// A reflector/module pair streamed to a client, written `M17-XOR/A`.
+/// `*` as module matches all modules of the reflector.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct StreamTarget {
+    pub(crate) reflector: String,
+    pub(crate) module: String,
+}
+
+impl StreamTarget {
+    fn parse(target: &str) -> Option<StreamTarget> {
+        let (reflector, module) = target.split_once('/')?;
+        if reflector.is_empty() || module.is_empty() || module.contains('/') {
+            return None;
+        }
+        Some(StreamTarget { reflector: reflector.to_string(), module: module.to_string() })
+    }
+
+    pub(crate) fn matches(&self, reflector: &str, module: &str) -> bool {
+        self.reflector == reflector && (self.is_wildcard() || self.module == module)
+    }
+
+    fn is_wildcard(&self) -> bool {
+        self.module == "*"
+    }
+}
+
+impl fmt::Display for StreamTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.reflector, self.module)
+    }
+}
+
+impl Serialize for StreamTarget {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
 }
 
 #[derive(Serialize)]
@@ -33,9 +112,18 @@ pub(crate) struct WsPayload {
     pub(crate) module: String,
     pub(crate) src_call: String,
     pub(crate) dest_call: String,
+    pub(crate) stream_id: Option<u16>,
+    pub(crate) frame_number: Option<u16>,
+    pub(crate) packet_protocol: Option<PacketProtocol>,
+    pub(crate) lsf: Lsf,
+    pub(crate) gnss: Option<GnssPosition>,
+    /// Format of the audio: `codec2` in `c2_stream`, otherwise in binary messages following this payload.
+    pub(crate) format: AudioFormat,
     pub(crate) c2_stream: Vec<u8>,
     pub(crate) pm_stream: Vec<u8>,
     pub(crate) done: bool,
+    /// Set for frames of past transmissions requested with the `replay` command.
+    pub(crate) replay: bool,
 }
 
 #[derive(Serialize)]
@@ -43,17 +131,199 @@ pub struct ModuleInfo {
     pub reflector: String,
     pub module: String,
     pub last_heard: u64,
+    pub linked: bool,
+    pub link_state: LinkState,
     pub last_qso_call: String,
     pub last_qso_time: u64,
+    pub last_qso_text: Option<String>,
     pub active_qso: bool,
-    pub messages: Vec<MsgData>,
+    pub active_qsos: Vec<QsoMeta>,
+    /// Number of retained messages, fetch them with the `messages` command.
+    pub message_count: usize,
+    pub last_message: Option<MsgData>,
+    pub positions: HashMap<String, PositionReport>,
+    pub link_stats: LinkStats,
 }
 
+/// Typed event pushed to info clients after the initial snapshot, tagged with a sequence number.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum InfoEvent {
+    QsoStart {
+        reflector: String,
+        module: String,
+        #[serde(flatten)]
+        qso: QsoMeta,
+    },
+    QsoEnd {
+        reflector: String,
+        module: String,
+        reason: QsoEndReason,
+        #[serde(flatten)]
+        qso: QsoMeta,
+    },
+    Position {
+        reflector: String,
+        module: String,
+        callsign: String,
+        #[serde(flatten)]
+        report: PositionReport,
+    },
+    Text {
+        reflector: String,
+        module: String,
+        callsign: String,
+        stream_id: u16,
+        text: String,
+        timestamp: u64,
+    },
+    Packet {
+        reflector: String,
+        module: String,
+        src_call: String,
+        dest_call: String,
+        protocol: PacketProtocol,
+        protocol_id: u8,
+        text: Option<String>,
+        data: Vec<u8>,
+        timestamp: u64,
+    },
+    MalformedPacket {
+        reflector: String,
+        module: String,
+        reason: String,
+        timestamp: u64,
+    },
+    Message {
+        reflector: String,
+        module: String,
+        #[serde(flatten)]
+        message: MsgData,
+    },
+    ModuleAdded {
+        #[serde(flatten)]
+        module: ModuleInfo,
+    },
+    ModuleRemoved {
+        reflector: String,
+        module: String,
+    },
+    LinkState {
+        reflector: String,
+        module: String,
+        linked: bool,
+        state: LinkState,
+        last_heard: u64,
+        link_stats: LinkStats,
+    },
+}
 
+
+/// Replaces all subscriptions of a stream client with a single module.
 #[derive(Deserialize)]
 pub(crate) struct ClientSubscription {
     pub(crate) reflector: String,
-    pub(crate) module: String
+    pub(crate) module: String,
+    /// Switch the audio format along with the subscription, keeps the current one if unset.
+    #[serde(default)]
+    pub(crate) format: Option<AudioFormat>,
+}
+
+/// Commands a stream client can send as JSON text messages.
+#[derive(Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+enum ClientCommand {
+    TxAuth { callsign: String, token: String },
+    TxEnd,
+    Sms {
+        reflector: String,
+        module: String,
+        message: String,
+        #[serde(default = "default_destination")]
+        destination: String,
+    },
+    LastHeard { limit: Option<usize> },
+    History(HistoryQuery),
+    /// Page through the retained messages of a module, newest first.
+    Messages {
+        reflector: String,
+        module: String,
+        /// Only messages with a lower ID, the `id` of the oldest message of the previous page.
+        before: Option<u64>,
+        limit: Option<usize>,
+    },
+    /// Add modules to the subscriptions, e.g. `["M17-XOR/A", "M17-ABC/*"]`.
+    Subscribe {
+        targets: Vec<String>,
+        /// Switch the audio format along with the subscriptions, keeps the current one if unset.
+        #[serde(default)]
+        format: Option<AudioFormat>,
+    },
+    /// Remove modules from the subscriptions, given as in `subscribe`.
+    Unsubscribe { targets: Vec<String> },
+    /// Replay the last `count` transmissions, of the subscribed module unless given.
+    Replay {
+        reflector: Option<String>,
+        module: Option<String>,
+        count: Option<usize>,
+    },
+}
+
+fn default_destination() -> String {
+    "ALL".to_string()
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ClientMessage {
+    Command(ClientCommand),
+    Subscription(ClientSubscription),
+}
+
+/// Replies sent to a stream client in response to its commands.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum SessionEvent {
+    TxAuth { callsign: String, success: bool },
+    TxError { reason: String },
+    SmsSent { reflector: String, module: String, destination: String, message: String },
+    SmsError { reason: String },
+    LastHeard { entries: Vec<LastHeard> },
+    History { qsos: Vec<QsoRecord>, messages: Vec<MessageRecord> },
+    HistoryError { reason: String },
+    Messages {
+        reflector: String,
+        module: String,
+        #[serde(flatten)]
+        page: MessagePage,
+    },
+    MessagesError { reason: String },
+    Replay { reflector: String, module: String, transmissions: usize },
+    ReplayDone { reflector: String, module: String },
+    ReplayError { reason: String },
+    /// The current subscriptions, after a `subscribe` or `unsubscribe` command.
+    Subscriptions { subscriptions: Vec<StreamTarget> },
+    /// A subscription or message was rejected.
+    Error { error: ErrorCode, reason: String },
+}
+
+/// Why a subscription or message was rejected.
+#[derive(Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+enum ErrorCode {
+    UnknownReflector,
+    UnknownModule,
+    BadRequest,
+}
+
+impl ErrorCode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::UnknownReflector => "unknown_reflector",
+            ErrorCode::UnknownModule => "unknown_module",
+            ErrorCode::BadRequest => "bad_request",
+        }
+    }
 }
 
 #[async_trait]
@@ -68,47 +338,82 @@ impl ezsockets::ServerExt for M17ClientServer {
         request: Request,
         address: SocketAddr,
     ) -> Result<Session, Option<CloseFrame>> {
-        let id = address.port();
+        let id = NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed);
         let session = Session::create(|handle| WebSocketClientSession { id, handle }, id, socket);
 
         let is_info: bool;
+        let mut resume = None;
+        let mut format = AudioFormat::Codec2;
 
-        let mut sub_ref = "";
-        let mut sub_mod = "";
+        let mut subscriptions = BTreeSet::new();
 
         match request.uri().path() {
             "/" => {
+                // Reconnecting clients pass the run and last sequence number they saw as ?run=<run>&since=<seq>
+                let run = query_param(&request, "run").and_then(|run| run.parse::<u64>().ok());
+                let since = query_param(&request, "since").and_then(|seq| seq.parse::<u64>().ok());
+                resume = run.zip(since);
                 info!("WS_CONNECTION {} connected as info client from {}", id, address);
-                // Send init module info
-                session.text(serde_json::to_string(&get_module_infos().await).unwrap()).unwrap();
                 is_info = true;
             },
             _ => {
-                let mut path = request.uri().path().split("/");
-                // TODO-> Check if path and module are ok
-                _ = path.next().unwrap();
-                sub_ref = path.next().unwrap();
-                sub_mod = path.next().unwrap();
+                let client = WebSocketClientSession { id, handle: session.clone() };
+                let segments: Vec<&str> = request.uri().path().trim_matches('/').split('/').collect();
+                let target = match segments.as_slice() {
+                    [reflector, module] => StreamTarget::parse(&format!("{}/{}", reflector, module)),
+                    _ => None,
+                };
+                let Some(target) = target else {
+                    warn!("WS_CONNECTION {} from {} requested invalid path {}", id, address, request.uri().path());
+                    client.reject(ErrorCode::BadRequest, "Expected /<Designator>/<Module> or /".to_string());
+                    return Ok(session);
+                };
+
+                if let Err((error, reason)) = check_target(&target).await {
+                    warn!("WS_CONNECTION {} from {} rejected: {}", id, address, reason);
+                    client.reject(error, reason);
+                    return Ok(session);
+                }
+
+                if let Some(name) = query_param(&request, "format") {
+                    format = match AudioFormat::parse(name) {
+                        Some(requested) if requested.available() => requested,
+                        _ => {
+                            warn!("WS_CONNECTION {} requested unsupported audio format {}, sending Codec2", id, name);
+                            AudioFormat::Codec2
+                        }
+                    };
+                }
 
-                info!("WS_CONNECTION {} connected as stream client from {} subscribing Reflector {} Module {}", id, address, sub_ref, sub_mod);
+                info!("WS_CONNECTION {} connected as stream client from {} subscribing {} ({:?})", id, address, target, format);
+                subscriptions.insert(target);
                 is_info = false;
             }
         }
 
-        WS_SESSIONS.lock().await.push(
-            M17ClientSession {
-                ws_session: WebSocketClientSession {
-                    handle: session.clone(),
-                    id,
-                }
-                ,
-                subscription: ClientSubscription {
-                    reflector: sub_ref.to_string(),
-                    module: sub_mod.to_string()
-                },
-                info_connection: is_info
+        let client_session = M17ClientSession {
+            ws_session: WebSocketClientSession {
+                handle: session.clone(),
+                id,
             }
-        );
+            ,
+            subscriptions,
+            info_connection: is_info,
+            tx_callsign: None,
+            format,
+            remote_address: address,
+            user_agent: request.headers().get("user-agent")
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string),
+            connected_at: get_epoch().as_secs(),
+        };
+
+        if is_info {
+            // Send init module info or the missed events
+            attach_info_client(client_session, resume).await;
+        } else {
+            WS_SESSIONS.lock().await.insert(id, client_session);
+        }
         Ok(session)
     }
 
@@ -117,9 +422,11 @@ impl ezsockets::ServerExt for M17ClientServer {
         id: <Self::Session as ezsockets::SessionExt>::ID,
         _reason: Result<Option<CloseFrame>, Error>,
     ) -> Result<(), Error> {
-        let index = WS_SESSIONS.lock().await.iter().position(|x| x.ws_session.id == id).unwrap();
-        info!("WS_SESSION {} disconnected", index);
-        WS_SESSIONS.lock().await.remove(index);
+        end_transmission(id).await;
+        // Rejected clients were never registered
+        if let Some(session) = WS_SESSIONS.lock().await.remove(&id) {
+            info!("WS_CONNECTION {} from {} disconnected after {} seconds", id, session.remote_address, get_epoch().as_secs().saturating_sub(session.connected_at));
+        }
         Ok(())
     }
 
@@ -139,23 +446,349 @@ impl ezsockets::SessionExt for WebSocketClientSession {
     }
 
     async fn on_text(&mut self, text: Utf8Bytes) -> Result<(), Error> {
-        let payload: ClientSubscription = serde_json::from_str(&text).unwrap();
-        info!("New subscription to stream from WS_CONNECTION {}: Reflector {} Module {}", self.id, payload.reflector.clone(), payload.module.clone());
-
-        let mut ws_sessions = WS_SESSIONS.lock().await;
-        for session in ws_sessions.iter_mut() {
-            if session.ws_session.id == self.id {
-                if session.info_connection {
-                    warn!("Stream subscription with info client failed!")
+        let payload = match serde_json::from_str::<ClientMessage>(&text) {
+            Ok(ClientMessage::Subscription(payload)) => payload,
+            Ok(ClientMessage::Command(command)) => return self.handle_command(command).await,
+            Err(e) => {
+                warn!("Invalid message from WS_CONNECTION {}: {}", self.id, e);
+                self.send_event(&SessionEvent::Error { error: ErrorCode::BadRequest, reason: e.to_string() });
+                return Ok(());
+            }
+        };
+        let target = StreamTarget { reflector: payload.reflector, module: payload.module };
+        info!("New subscription to stream from WS_CONNECTION {}: {}", self.id, target);
+        // Checked before check_target, so info clients cannot link on-demand modules
+        if !self.stream_client().await {
+            return Ok(());
+        }
+        if let Err((error, reason)) = check_target(&target).await {
+            warn!("Subscription of WS_CONNECTION {} rejected: {}", self.id, reason);
+            self.send_event(&SessionEvent::Error { error, reason });
+            return Ok(());
+        }
+
+        self.update_subscriptions(payload.format, |subscriptions| {
+            subscriptions.clear();
+            subscriptions.insert(target);
+        }).await;
+        Ok(())
+    }
+
+    async fn on_binary(&mut self, bytes: ezsockets::Bytes) -> Result<(), Error> {
+        let (target, tx_callsign) = match WS_SESSIONS.lock().await.get(&self.id) {
+            Some(session) if !session.info_connection => (
+                session.single_module().cloned(),
+                session.tx_callsign.clone(),
+            ),
+            _ => return Ok(()),
+        };
+
+        let Some(callsign) = tx_callsign else {
+            self.send_event(&SessionEvent::TxError { reason: "Not authenticated for transmitting".to_string() });
+            return Ok(());
+        };
+
+        let Some(target) = target else {
+            self.send_event(&SessionEvent::TxError { reason: "Transmitting requires a subscription to exactly one module".to_string() });
+            return Ok(());
+        };
+
+        if let Err(reason) = transmit_audio(self.id, &callsign, &target.reflector, &target.module, &bytes).await {
+            warn!("Transmission from WS_CONNECTION {} failed: {}", self.id, reason);
+            end_transmission(self.id).await;
+            self.send_event(&SessionEvent::TxError { reason });
+        }
+        Ok(())
+    }
+
+    async fn on_call(&mut self, _call: Self::Call) -> Result<(), Error> { Ok(()) }
+}
+
+impl WebSocketClientSession {
+    async fn handle_command(&mut self, command: ClientCommand) -> Result<(), Error> {
+        match command {
+            ClientCommand::TxAuth { callsign, token } => {
+                let callsign = callsign.trim().to_uppercase();
+                let success = check_credentials(&callsign, &token);
+                if success {
+                    info!("WS_CONNECTION {} authenticated for transmitting as {}", self.id, callsign);
                 } else {
-                    session.subscription.reflector = payload.reflector.clone();
-                    session.subscription.module = payload.module.clone();
+                    warn!("WS_CONNECTION {} failed to authenticate as {}", self.id, callsign);
                 }
+
+                if let Some(session) = WS_SESSIONS.lock().await.get_mut(&self.id) {
+                    session.tx_callsign = success.then(|| callsign.clone());
+                }
+                self.send_event(&SessionEvent::TxAuth { callsign, success });
+            }
+            ClientCommand::TxEnd => {
+                end_transmission(self.id).await;
             }
+            ClientCommand::Sms { reflector, module, message, destination } => {
+                let tx_callsign = WS_SESSIONS.lock().await.get(&self.id)
+                    .and_then(|x| x.tx_callsign.clone());
+                let Some(callsign) = tx_callsign else {
+                    self.send_event(&SessionEvent::SmsError { reason: "Not authenticated for transmitting".to_string() });
+                    return Ok(());
+                };
 
+                let destination = destination.trim().to_uppercase();
+                match send_sms(&callsign, &destination, &reflector, &module, &message).await {
+                    Ok(()) => {
+                        record_message(MessageRecord {
+                            reflector: reflector.clone(),
+                            module: module.clone(),
+                            src_call: callsign.clone(),
+                            dst_call: destination.clone(),
+                            protocol: PacketProtocol::Sms.name().to_string(),
+                            text: Some(message.clone()),
+                            data: message.as_bytes().to_vec(),
+                            timestamp: get_epoch().as_secs(),
+                        });
+                        // The reflector does not echo our own packets, record the message locally
+                        add_message(&reflector, &module, MsgData::new(callsign, message.clone())).await;
+                        self.send_event(&SessionEvent::SmsSent { reflector, module, destination, message });
+                    }
+                    Err(reason) => {
+                        warn!("SMS from WS_CONNECTION {} failed: {}", self.id, reason);
+                        self.send_event(&SessionEvent::SmsError { reason });
+                    }
+                }
+            }
+            ClientCommand::LastHeard { limit } => {
+                let limit = limit.unwrap_or(20);
+                match query(move |store| store.last_heard(limit)).await {
+                    Ok(entries) => self.send_event(&SessionEvent::LastHeard { entries }),
+                    Err(reason) => self.send_event(&SessionEvent::HistoryError { reason }),
+                }
+            }
+            ClientCommand::History(history_query) => {
+                let result = query(move |store| {
+                    Ok((store.qsos(&history_query)?, store.messages(&history_query)?))
+                }).await;
+                match result {
+                    Ok((qsos, messages)) => self.send_event(&SessionEvent::History { qsos, messages }),
+                    Err(reason) => self.send_event(&SessionEvent::HistoryError { reason }),
+                }
+            }
+            ClientCommand::Messages { reflector, module, before, limit } => {
+                match message_page(&reflector, &module, before, limit).await {
+                    Some(page) => self.send_event(&SessionEvent::Messages { reflector, module, page }),
+                    None => self.send_event(&SessionEvent::MessagesError {
+                        reason: format!("Not subscribed to {} Module {}", reflector, module),
+                    }),
+                }
+            }
+            ClientCommand::Subscribe { targets, format } => {
+                if !self.stream_client().await {
+                    return Ok(());
+                }
+                let mut accepted = vec![];
+                for target in self.parse_targets(&targets) {
+                    match check_target(&target).await {
+                        Ok(()) => accepted.push(target),
+                        Err((error, reason)) => {
+                            warn!("Subscription of WS_CONNECTION {} rejected: {}", self.id, reason);
+                            self.send_event(&SessionEvent::Error { error, reason });
+                        }
+                    }
+                }
+                if !accepted.is_empty() {
+                    info!("WS_CONNECTION {} subscribed to {}", self.id, accepted.iter().map(ToString::to_string).collect::<Vec<_>>().join(", "));
+                }
+                if let Some(subscriptions) = self.update_subscriptions(format, |subscriptions| subscriptions.extend(accepted)).await {
+                    self.send_event(&SessionEvent::Subscriptions { subscriptions });
+                }
+            }
+            ClientCommand::Unsubscribe { targets } => {
+                if !self.stream_client().await {
+                    return Ok(());
+                }
+                let removed = self.parse_targets(&targets);
+                info!("WS_CONNECTION {} unsubscribed from {}", self.id, removed.iter().map(ToString::to_string).collect::<Vec<_>>().join(", "));
+                let update = |subscriptions: &mut BTreeSet<StreamTarget>| subscriptions.retain(|target| !removed.contains(target));
+                if let Some(subscriptions) = self.update_subscriptions(None, update).await {
+                    self.send_event(&SessionEvent::Subscriptions { subscriptions });
+                }
+            }
+            ClientCommand::Replay { reflector, module, count } => {
+                let session = WS_SESSIONS.lock().await.get(&self.id)
+                    .filter(|x| !x.info_connection)
+                    .map(|x| (x.single_module().cloned(), x.format));
+                let Some((single, format)) = session else {
+                    self.send_event(&SessionEvent::ReplayError { reason: "Replay is only available to stream clients".to_string() });
+                    return Ok(());
+                };
+                let (reflector, module) = match (reflector, module, single) {
+                    (Some(reflector), Some(module), _) => (reflector, module),
+                    (reflector, module, Some(target)) => (reflector.unwrap_or(target.reflector), module.unwrap_or(target.module)),
+                    _ => {
+                        self.send_event(&SessionEvent::ReplayError { reason: "Specify reflector and module when subscribed to several modules".to_string() });
+                        return Ok(());
+                    }
+                };
+
+                let Some(transmissions) = recent_transmissions(&reflector, &module, count.unwrap_or(1)).await else {
+                    self.send_event(&SessionEvent::ReplayError {
+                        reason: format!("Not subscribed to {} Module {}", reflector, module),
+                    });
+                    return Ok(());
+                };
+                info!("Replaying {} transmissions of {} Module {} to WS_CONNECTION {}", transmissions.len(), reflector, module, self.id);
+                self.send_event(&SessionEvent::Replay { reflector: reflector.clone(), module: module.clone(), transmissions: transmissions.len() });
+
+                // Pace the frames in the background, so the session keeps handling commands
+                let handle = self.handle.clone();
+                tokio::spawn(async move {
+                    if replay(&handle, &reflector, &module, format, transmissions).await {
+                        let text = serde_json::to_string(&SessionEvent::ReplayDone { reflector, module }).unwrap();
+                        metrics::bytes_sent("reply", text.len());
+                        let _ = handle.text(text);
+                    }
+                });
+            }
         }
         Ok(())
     }
-    async fn on_binary(&mut self, _bytes: ezsockets::Bytes) -> Result<(), Error> { unimplemented!() }
-    async fn on_call(&mut self, _call: Self::Call) -> Result<(), Error> { unimplemented!() }
+
+    fn send_event(&self, event: &SessionEvent) {
+        let text = serde_json::to_string(event).unwrap();
+        metrics::bytes_sent("reply", text.len());
+        let _ = self.handle.text(text);
+    }
+
+    /// Parse `<Designator>/<Module>` targets, reporting the malformed ones.
+    fn parse_targets(&self, targets: &[String]) -> Vec<StreamTarget> {
+        targets.iter()
+            .filter_map(|target| {
+                let parsed = StreamTarget::parse(target);
+                if parsed.is_none() {
+                    self.send_event(&SessionEvent::Error {
+                        error: ErrorCode::BadRequest,
+                        reason: format!("Expected <Designator>/<Module>, got {}", target),
+                    });
+                }
+                parsed
+            })
+            .collect()
+    }
+
+    /// Whether this is a registered stream client, telling info clients they cannot subscribe.
+    async fn stream_client(&self) -> bool {
+        let info_connection = WS_SESSIONS.lock().await.get(&self.id).is_none_or(|x| x.info_connection);
+        if info_connection {
+            warn!("Stream subscription with info client failed!");
+            self.send_event(&SessionEvent::Error { error: ErrorCode::BadRequest, reason: "Info clients cannot subscribe to streams".to_string() });
+        }
+        !info_connection
+    }
+
+    /// Change the subscriptions and optionally the audio format of a stream client.
+    /// Returns the resulting subscriptions, or None for info clients.
+    async fn update_subscriptions(&self, format: Option<AudioFormat>, update: impl FnOnce(&mut BTreeSet<StreamTarget>)) -> Option<Vec<StreamTarget>> {
+        let mut sessions = WS_SESSIONS.lock().await;
+        let session = sessions.get_mut(&self.id).filter(|x| !x.info_connection)?;
+
+        update(&mut session.subscriptions);
+        match format {
+            Some(format) if format.available() => session.format = format,
+            Some(format) => warn!("Audio format {:?} is not supported by this build", format),
+            None => {}
+        }
+        Some(session.subscriptions.iter().cloned().collect())
+    }
+
+    /// Send an error to a client that cannot be served and close the connection.
+    fn reject(&self, error: ErrorCode, reason: String) {
+        self.send_event(&SessionEvent::Error { error, reason });
+        let _ = self.handle.close(Some(CloseFrame {
+            code: CloseCode::Policy,
+            reason: error.as_str().into(),
+        }));
+    }
+}
+
+/// Number of WebSocket connections accepted since startup.
+pub(crate) fn connections_accepted() -> u64 {
+    NEXT_SESSION_ID.load(Ordering::Relaxed) - 1
+}
+
+/// Link the target on demand if allowed, then check that it names a subscribed module,
+/// or a subscribed reflector for wildcards.
+async fn check_target(target: &StreamTarget) -> Result<(), (ErrorCode, String)> {
+    if !target.is_wildcard() {
+        request_module(&target.reflector, &target.module).await;
+    }
+    let connections = REFLECTOR_CONNECTIONS.lock().await;
+    if !connections.iter().any(|c| c.reflector == target.reflector) {
+        return Err((ErrorCode::UnknownReflector, format!("Reflector {} is not subscribed", target.reflector)));
+    }
+    if !target.is_wildcard() && !connections.iter().any(|c| target.matches(&c.reflector, &c.module)) {
+        return Err((ErrorCode::UnknownModule, format!("Module {} of {} is not subscribed", target.module, target.reflector)));
+    }
+    Ok(())
+}
+
+/// Send a stream payload to a client, followed by its decoded audio in binary messages.
+/// Returns false if the client is gone.
+pub(crate) fn send_stream_frame(handle: &Session, payload: &WsPayload, pcm: Option<&[i16]>, opus: &[Vec<u8>]) -> bool {
+    let text = serde_json::to_string(payload).unwrap();
+    metrics::bytes_sent("stream", text.len());
+    if handle.text(text).is_err() {
+        return false;
+    }
+    match (payload.format, pcm) {
+        (AudioFormat::Pcm16, Some(pcm)) => {
+            let bytes = pcm_bytes(pcm);
+            metrics::bytes_sent("stream", bytes.len());
+            let _ = handle.binary(bytes);
+        }
+        (AudioFormat::Opus, _) => {
+            for packet in opus {
+                metrics::bytes_sent("stream", packet.len());
+                let _ = handle.binary(packet.clone());
+            }
+        }
+        _ => {}
+    }
+    true
+}
+
+/// Value of a query string parameter of the upgrade request.
+fn query_param<'a>(request: &'a Request, name: &str) -> Option<&'a str> {
+    request.uri().query()?.split('&').find_map(|param| {
+        param.split_once('=').filter(|(key, _)| *key == name).map(|(_, value)| value)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stream_target_parse() {
+        let target = StreamTarget::parse("M17-XOR/A").unwrap();
+        assert_eq!((target.reflector.as_str(), target.module.as_str()), ("M17-XOR", "A"));
+        assert!(!target.is_wildcard());
+        assert_eq!(target.to_string(), "M17-XOR/A");
+        assert_eq!(serde_json::to_string(&target).unwrap(), "\"M17-XOR/A\"");
+
+        assert!(StreamTarget::parse("M17-XOR/*").unwrap().is_wildcard());
+        for invalid in ["", "M17-XOR", "M17-XOR/", "/A", "M17-XOR/A/B"] {
+            assert!(StreamTarget::parse(invalid).is_none(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn stream_target_matches() {
+        let target = StreamTarget::parse("M17-XOR/A").unwrap();
+        assert!(target.matches("M17-XOR", "A"));
+        assert!(!target.matches("M17-XOR", "B"));
+        assert!(!target.matches("M17-ABC", "A"));
+
+        let wildcard = StreamTarget::parse("M17-XOR/*").unwrap();
+        assert!(wildcard.matches("M17-XOR", "A"));
+        assert!(wildcard.matches("M17-XOR", "Z"));
+        assert!(!wildcard.matches("M17-ABC", "A"));
+    }
 }